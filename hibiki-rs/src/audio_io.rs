@@ -7,6 +7,8 @@ use anyhow::{Context, Result};
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
+use crate::stream::resampler::InputChannel;
+
 pub const SAMPLE_RATE: usize = 24_000;
 
 pub(crate) struct AudioOutputData_ {
@@ -128,21 +130,48 @@ impl AudioOutputData_ {
 
 type AudioOutputData = Arc<Mutex<AudioOutputData_>>;
 
-fn conv<T>(samples: &mut Vec<f32>, data: std::borrow::Cow<symphonia::core::audio::AudioBuffer<T>>)
+fn conv<T>(
+    samples: &mut Vec<f32>,
+    data: std::borrow::Cow<symphonia::core::audio::AudioBuffer<T>>,
+    channel: InputChannel,
+) -> Result<()>
 where
     T: symphonia::core::sample::Sample,
     f32: symphonia::core::conv::FromSample<T>,
 {
     use symphonia::core::audio::Signal;
     use symphonia::core::conv::FromSample;
-    samples.extend(data.chan(0).iter().map(|v| f32::from_sample(*v)))
+    let channels = data.spec().channels.count();
+    channel.validate(channels)?;
+    samples.extend(
+        (0..data.frames())
+            .map(|i| channel.pick((0..channels).map(|ch| f32::from_sample(data.chan(ch)[i])))),
+    );
+    Ok(())
+}
+
+/// Decodes a file to mono, mixing the channels or keeping the selected one.
+pub(crate) fn pcm_decode<P: AsRef<std::path::Path>>(
+    path: P,
+    channel: InputChannel,
+) -> Result<(Vec<f32>, u32)> {
+    let src = std::fs::File::open(path)?;
+    decode_media(Box::new(src), channel)
 }
 
-pub(crate) fn pcm_decode<P: AsRef<std::path::Path>>(path: P) -> Result<(Vec<f32>, u32)> {
+/// Same as [`pcm_decode`] for a file held in memory, e.g. received over the
+/// network.
+pub(crate) fn pcm_decode_bytes(data: Vec<u8>, channel: InputChannel) -> Result<(Vec<f32>, u32)> {
+    decode_media(Box::new(std::io::Cursor::new(data)), channel)
+}
+
+fn decode_media(
+    src: Box<dyn symphonia::core::io::MediaSource>,
+    channel: InputChannel,
+) -> Result<(Vec<f32>, u32)> {
     use symphonia::core::audio::{AudioBufferRef, Signal};
 
-    let src = std::fs::File::open(path)?;
-    let mss = symphonia::core::io::MediaSourceStream::new(Box::new(src), Default::default());
+    let mss = symphonia::core::io::MediaSourceStream::new(src, Default::default());
     let hint = symphonia::core::probe::Hint::new();
     let meta_opts: symphonia::core::meta::MetadataOptions = Default::default();
     let fmt_opts: symphonia::core::formats::FormatOptions = Default::default();
@@ -152,10 +181,10 @@ pub(crate) fn pcm_decode<P: AsRef<std::path::Path>>(path: P) -> Result<(Vec<f32>
         .tracks()
         .iter()
         .find(|t| t.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
-        .expect("no supported audio tracks");
+        .context("no supported audio tracks")?;
     let mut decoder = symphonia::default::get_codecs()
         .make(&track.codec_params, &Default::default())
-        .expect("unsupported codec");
+        .context("unsupported codec")?;
     let track_id = track.id;
     let sample_rate = track.codec_params.sample_rate.unwrap_or(0);
     let mut pcm_data = Vec::new();
@@ -167,16 +196,16 @@ pub(crate) fn pcm_decode<P: AsRef<std::path::Path>>(path: P) -> Result<(Vec<f32>
             continue;
         }
         match decoder.decode(&packet)? {
-            AudioBufferRef::F32(buf) => pcm_data.extend(buf.chan(0)),
-            AudioBufferRef::U8(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::U16(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::U24(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::U32(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::S8(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::S16(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::S24(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::S32(data) => conv(&mut pcm_data, data),
-            AudioBufferRef::F64(data) => conv(&mut pcm_data, data),
+            AudioBufferRef::F32(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::U8(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::U16(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::U24(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::U32(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::S8(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::S16(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::S24(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::S32(data) => conv(&mut pcm_data, data, channel)?,
+            AudioBufferRef::F64(data) => conv(&mut pcm_data, data, channel)?,
         }
     }
     Ok((pcm_data, sample_rate))
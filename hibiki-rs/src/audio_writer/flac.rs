@@ -0,0 +1,162 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{anyhow, bail, Result};
+use flacenc::bitsink::MemSink;
+use flacenc::component::{BitRepr, StreamInfo};
+use flacenc::error::{Verified, Verify};
+use flacenc::source::{Context, Fill, FrameBuf};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::dither_f32;
+use crate::stream::resampler::TARGET_SAMPLE_RATE;
+
+pub const FLAC_BITS_PER_SAMPLE: [u32; 2] = [16, 24];
+/// Samples per FLAC frame, the reference encoder default.
+const BLOCK_SIZE: usize = 4096;
+/// "fLaC" followed by the header of the STREAMINFO block.
+const STREAM_INFO_OFFSET: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlacConfig {
+    pub bits_per_sample: u32,
+}
+
+impl Default for FlacConfig {
+    fn default() -> Self {
+        Self { bits_per_sample: 16 }
+    }
+}
+
+impl FlacConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !FLAC_BITS_PER_SAMPLE.contains(&self.bits_per_sample) {
+            bail!("unsupported flac bit depth {}, expected 16 or 24", self.bits_per_sample)
+        }
+        Ok(())
+    }
+}
+
+/// Lossless 24kHz mono FLAC, dithered to the configured bit depth.
+///
+/// Frames are encoded as the audio comes in, the STREAMINFO block holding the
+/// total length and checksum is rewritten once the stream ends.
+pub struct FlacWriter<W: Write + Seek> {
+    out: W,
+    encoder: Verified<flacenc::config::Encoder>,
+    stream_info: StreamInfo,
+    context: Context,
+    framebuf: FrameBuf,
+    bits_per_sample: u32,
+    /// Quantized samples that do not fill a frame yet.
+    pending: Vec<i32>,
+    frames: usize,
+    rng: u32,
+    samples: usize,
+}
+
+impl FlacWriter<std::io::BufWriter<std::fs::File>> {
+    pub fn create(path: &Path, config: &FlacConfig) -> Result<Self> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        Self::new(file, config)
+    }
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(mut out: W, config: &FlacConfig) -> Result<Self> {
+        config.validate()?;
+        let bits = config.bits_per_sample as usize;
+        let encoder = flacenc::config::Encoder::default()
+            .into_verified()
+            .map_err(|(_, e)| anyhow!("invalid flac encoder config: {e}"))?;
+        let stream_info = StreamInfo::new(TARGET_SAMPLE_RATE, 1, bits)?;
+        out.write_all(b"fLaC")?;
+        write_stream_info(&mut out, &stream_info)?;
+        Ok(Self {
+            out,
+            encoder,
+            stream_info,
+            context: Context::new(bits, 1),
+            framebuf: FrameBuf::with_size(1, BLOCK_SIZE)?,
+            bits_per_sample: config.bits_per_sample,
+            pending: Vec::with_capacity(BLOCK_SIZE),
+            frames: 0,
+            // Seed for dither
+            rng: 0x12345678,
+            samples: 0,
+        })
+    }
+
+    /// Number of samples written so far.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn write(&mut self, pcm: &[f32]) -> Result<()> {
+        for &sample in pcm {
+            self.pending.push(dither_f32(sample, self.bits_per_sample, &mut self.rng));
+            if self.pending.len() == BLOCK_SIZE {
+                self.encode_pending()?;
+            }
+        }
+        self.samples += pcm.len();
+        Ok(())
+    }
+
+    fn encode_pending(&mut self) -> Result<()> {
+        // The flac errors are not Send, so they get converted to strings.
+        self.framebuf.fill_interleaved(&self.pending).map_err(|e| anyhow!("{e}"))?;
+        self.context.fill_interleaved(&self.pending).map_err(|e| anyhow!("{e}"))?;
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.encoder,
+            &self.framebuf,
+            self.frames,
+            &self.stream_info,
+        )
+        .map_err(|e| anyhow!("cannot encode flac frame: {e}"))?;
+        self.stream_info.update_frame_info(&frame);
+        let mut sink = MemSink::<u8>::with_capacity(frame.count_bits());
+        frame.write(&mut sink).map_err(|e| anyhow!("cannot write flac frame: {e}"))?;
+        self.out.write_all(sink.as_slice())?;
+        self.frames += 1;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Encodes the remaining audio and completes the header, returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.pending.is_empty() {
+            self.encode_pending()?;
+        }
+        if self.frames == 0 {
+            self.stream_info.set_frame_sizes(0, 0)?;
+        }
+        // The minimum block size must not account for the last block, decoders
+        // would otherwise treat the stream as variable-blocksize.
+        self.stream_info.set_block_sizes(BLOCK_SIZE, BLOCK_SIZE)?;
+        self.stream_info.set_md5_digest(&self.context.md5_digest());
+        self.out.seek(SeekFrom::Start(STREAM_INFO_OFFSET))?;
+        write_stream_info_body(&mut self.out, &self.stream_info)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Writes the STREAMINFO metadata block, flagged as the last one.
+fn write_stream_info<W: Write>(out: &mut W, info: &StreamInfo) -> Result<()> {
+    let len = (info.count_bits() / 8) as u32;
+    out.write_all(&[0x80])?;
+    out.write_all(&len.to_be_bytes()[1..])?;
+    write_stream_info_body(out, info)
+}
+
+fn write_stream_info_body<W: Write>(out: &mut W, info: &StreamInfo) -> Result<()> {
+    let mut sink = MemSink::<u8>::new();
+    info.write(&mut sink).map_err(|e| anyhow!("cannot write flac header: {e}"))?;
+    out.write_all(sink.as_slice())?;
+    Ok(())
+}
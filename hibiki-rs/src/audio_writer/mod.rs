@@ -0,0 +1,148 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Writers for the generated audio, in the format picked from the file
+//! extension or set explicitly.
+
+use anyhow::{bail, Result};
+use std::path::Path;
+
+mod flac;
+mod opus;
+mod stereo;
+mod wav;
+
+pub use flac::{FlacConfig, FlacWriter, FLAC_BITS_PER_SAMPLE};
+pub use opus::{OggOpusWriter, OpusConfig, DEFAULT_OPUS_BITRATE, OPUS_SAMPLE_RATES};
+pub use stereo::StereoWriter;
+pub use wav::{WavConfig, WavSampleFormat, WavWriter};
+
+/// Container and codec of a saved audio file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// PCM WAV, 16-bit at 24kHz by default.
+    Wav,
+    /// Opus in an Ogg container.
+    Opus,
+    /// Lossless FLAC at 24kHz, 16 or 24-bit.
+    Flac,
+}
+
+impl OutputFormat {
+    /// Guesses the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "wav" => Some(Self::Wav),
+            "opus" | "ogg" | "oga" => Some(Self::Opus),
+            "flac" => Some(Self::Flac),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Ok(Self::Wav),
+            "opus" | "ogg" => Ok(Self::Opus),
+            "flac" => Ok(Self::Flac),
+            _ => bail!("unknown output format {s}, expected wav, opus or flac"),
+        }
+    }
+}
+
+/// How the generated audio gets saved.
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    /// Overrides the format given by the file extension, files with an unknown
+    /// extension are written as WAV.
+    pub format: Option<OutputFormat>,
+    pub wav: WavConfig,
+    pub opus: OpusConfig,
+    pub flac: FlacConfig,
+}
+
+impl OutputConfig {
+    pub fn format_for(&self, path: &Path) -> OutputFormat {
+        self.format.or_else(|| OutputFormat::from_path(path)).unwrap_or(OutputFormat::Wav)
+    }
+
+    /// Checks the settings before any file gets created.
+    pub fn validate(&self) -> Result<()> {
+        self.wav.validate()?;
+        self.opus.validate()?;
+        self.flac.validate()
+    }
+}
+
+/// TPDF dither of a sample to a signed integer of the given bit depth.
+fn dither_f32(sample: f32, bits: u32, rng: &mut u32) -> i32 {
+    // TPDF: sum of two uniform random numbers
+    let r1 = (*rng as f32 / u32::MAX as f32) - 0.5;
+    *rng = rng.wrapping_mul(1103515245).wrapping_add(12345); // Simple LCG
+    let r2 = (*rng as f32 / u32::MAX as f32) - 0.5;
+    *rng = rng.wrapping_mul(1103515245).wrapping_add(12345);
+
+    // One least significant bit of the target depth.
+    let scale = (1u32 << (bits - 1)) as f32;
+    let dithered = sample + (r1 + r2) / scale;
+    (dithered.clamp(-1.0, 1.0) * (scale - 1.0)) as i32
+}
+
+/// Writes 24kHz mono audio to a file as it gets generated.
+pub enum AudioWriter {
+    Wav(Box<WavWriter>),
+    Opus(Box<OggOpusWriter<std::io::BufWriter<std::fs::File>>>),
+    Flac(Box<FlacWriter<std::io::BufWriter<std::fs::File>>>),
+}
+
+impl AudioWriter {
+    pub fn create(path: &Path, config: &OutputConfig) -> Result<Self> {
+        let writer = match config.format_for(path) {
+            OutputFormat::Wav => Self::Wav(Box::new(WavWriter::create(path, &config.wav)?)),
+            OutputFormat::Opus => {
+                let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                Self::Opus(Box::new(OggOpusWriter::new(file, &config.opus)?))
+            }
+            OutputFormat::Flac => Self::Flac(Box::new(FlacWriter::create(path, &config.flac)?)),
+        };
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, pcm: &[f32]) -> Result<()> {
+        match self {
+            Self::Wav(w) => w.write(pcm),
+            Self::Opus(w) => w.write(pcm),
+            Self::Flac(w) => w.write(pcm),
+        }
+    }
+
+    /// Completes the file, returns the number of 24kHz samples written.
+    pub fn finish(self) -> Result<usize> {
+        match self {
+            Self::Wav(w) => w.finish(),
+            Self::Opus(w) => {
+                let samples = w.samples();
+                w.finish()?;
+                Ok(samples)
+            }
+            Self::Flac(w) => {
+                let samples = w.samples();
+                w.finish()?;
+                Ok(samples)
+            }
+        }
+    }
+}
+
+/// Writes a whole file of 24kHz mono audio.
+pub fn write_pcm(path: &Path, pcm: &[f32], config: &OutputConfig) -> Result<()> {
+    let mut writer = AudioWriter::create(path, config)?;
+    writer.write(pcm)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{bail, Result};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::Write;
+
+use crate::stream::resampler::{OutputResampler, TARGET_SAMPLE_RATE};
+
+/// Rates the opus encoder runs at.
+pub const OPUS_SAMPLE_RATES: [u32; 5] = [8_000, 12_000, 16_000, 24_000, 48_000];
+pub const DEFAULT_OPUS_BITRATE: u32 = 32_000;
+/// Granule positions in Ogg Opus always count 48kHz samples.
+const GRANULE_RATE: u64 = 48_000;
+/// Encoder lookahead at 48kHz (6.5ms), skipped by the decoders.
+const PRE_SKIP: u16 = 312;
+/// Packets hold 20ms of audio.
+const FRAMES_PER_SECOND: u32 = 50;
+const GRANULES_PER_PACKET: u64 = GRANULE_RATE / FRAMES_PER_SECOND as u64;
+const MAX_PACKET_BYTES: usize = 1276;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusConfig {
+    /// Target bitrate in bits per second.
+    pub bitrate: u32,
+    /// Rate the encoder runs at, the 24kHz audio is resampled when it differs.
+    pub sample_rate: u32,
+}
+
+impl Default for OpusConfig {
+    fn default() -> Self {
+        Self { bitrate: DEFAULT_OPUS_BITRATE, sample_rate: TARGET_SAMPLE_RATE as u32 }
+    }
+}
+
+impl OpusConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !OPUS_SAMPLE_RATES.contains(&self.sample_rate) {
+            bail!(
+                "unsupported opus sample rate {}, expected one of {OPUS_SAMPLE_RATES:?}",
+                self.sample_rate
+            )
+        }
+        if !(6_000..=510_000).contains(&self.bitrate) {
+            bail!("opus bitrate must be between 6000 and 510000, got {}", self.bitrate)
+        }
+        Ok(())
+    }
+}
+
+/// Encodes 24kHz mono audio as Opus in an Ogg container (RFC 7845).
+pub struct OggOpusWriter<W: Write> {
+    packets: PacketWriter<'static, W>,
+    serial: u32,
+    encoder: opus_rs::OpusEncoder,
+    resampler: Option<OutputResampler>,
+    sample_rate: u32,
+    /// Samples per packet at the encoder rate.
+    frame_size: usize,
+    /// Audio at the encoder rate that does not fill a packet yet.
+    pending: Vec<f32>,
+    /// Kept until the next one so that the last packet can end the stream.
+    last_packet: Option<Vec<u8>>,
+    packets_encoded: u64,
+    /// Samples written, at 24kHz and at the encoder rate.
+    samples: usize,
+    encoded_samples: u64,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    pub fn new(out: W, config: &OpusConfig) -> Result<Self> {
+        config.validate()?;
+        let mut encoder =
+            opus_rs::OpusEncoder::new(config.sample_rate as i32, 1, opus_rs::Application::Audio)
+                .map_err(anyhow::Error::msg)?;
+        encoder.bitrate_bps = config.bitrate as i32;
+        let resampler = if config.sample_rate as usize == TARGET_SAMPLE_RATE {
+            None
+        } else {
+            Some(OutputResampler::new(config.sample_rate as usize)?)
+        };
+        let serial = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let mut writer = Self {
+            packets: PacketWriter::new(out),
+            serial,
+            encoder,
+            resampler,
+            sample_rate: config.sample_rate,
+            frame_size: (config.sample_rate / FRAMES_PER_SECOND) as usize,
+            pending: vec![],
+            last_packet: None,
+            packets_encoded: 0,
+            samples: 0,
+            encoded_samples: 0,
+        };
+        writer.write_headers()?;
+        Ok(writer)
+    }
+
+    fn write_headers(&mut self) -> Result<()> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(1); // channels
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&(TARGET_SAMPLE_RATE as u32).to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        self.packets.write_packet(head, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("hibiki ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+        self.packets.write_packet(tags, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+        Ok(())
+    }
+
+    /// Number of 24kHz samples written so far.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn write(&mut self, pcm: &[f32]) -> Result<()> {
+        self.samples += pcm.len();
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let resampled = resampler.push_samples(pcm)?;
+                self.encoded_samples += resampled.len() as u64;
+                self.encode(&resampled)
+            }
+            None => {
+                self.encoded_samples += pcm.len() as u64;
+                self.encode(pcm)
+            }
+        }
+    }
+
+    fn encode(&mut self, pcm: &[f32]) -> Result<()> {
+        self.pending.extend_from_slice(pcm);
+        let mut pos = 0;
+        while self.pending.len() - pos >= self.frame_size {
+            let mut packet = [0u8; MAX_PACKET_BYTES];
+            let frame = &self.pending[pos..pos + self.frame_size];
+            let len = self
+                .encoder
+                .encode(frame, self.frame_size, &mut packet)
+                .map_err(anyhow::Error::msg)?;
+            pos += self.frame_size;
+            self.push_packet(packet[..len].to_vec())?;
+        }
+        self.pending.drain(..pos);
+        Ok(())
+    }
+
+    fn push_packet(&mut self, packet: Vec<u8>) -> Result<()> {
+        if let Some(previous) = self.last_packet.replace(packet) {
+            let granule = self.packets_encoded * GRANULES_PER_PACKET;
+            self.packets.write_packet(
+                previous,
+                self.serial,
+                PacketWriteEndInfo::NormalPacket,
+                granule,
+            )?;
+        }
+        self.packets_encoded += 1;
+        Ok(())
+    }
+
+    /// Encodes the remaining audio and ends the stream, returns the underlying
+    /// writer.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(mut resampler) = self.resampler.take() {
+            let rest = resampler.finish()?;
+            self.encoded_samples += rest.len() as u64;
+            self.encode(&rest)?;
+        }
+        // The end of the audio is only output by the decoder once the encoder
+        // lookahead has been flushed with some silence.
+        let end_granule =
+            PRE_SKIP as u64 + self.encoded_samples * GRANULE_RATE / self.sample_rate as u64;
+        while self.last_packet.is_none()
+            || !self.pending.is_empty()
+            || self.packets_encoded * GRANULES_PER_PACKET < end_granule
+        {
+            let padding = self.frame_size - self.pending.len();
+            self.encode(&vec![0.; padding])?;
+        }
+        if let Some(packet) = self.last_packet.take() {
+            self.packets.write_packet(
+                packet,
+                self.serial,
+                PacketWriteEndInfo::EndStream,
+                end_granule,
+            )?;
+        }
+        let mut out = self.packets.into_inner();
+        out.flush()?;
+        Ok(out)
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::Result;
+use std::path::Path;
+
+use super::dither_f32;
+use crate::stream::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+
+/// 16-bit stereo WAV at 24kHz holding the frames fed to the model on the left
+/// channel and the generated audio on the right one, so that the lag of the
+/// translation can be heard.
+///
+/// The audio generated at a step starts along with the input frame of that
+/// step, or right after the audio of the previous steps when it ran longer.
+pub struct StereoWriter {
+    writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    rng: u32,
+    /// Generated audio not written yet, starting at the current step.
+    pending: Vec<f32>,
+    samples: usize,
+}
+
+impl StereoWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: TARGET_SAMPLE_RATE as u32,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec)?;
+        // Seed for dither
+        Ok(Self { writer, rng: 0x12345678, pending: vec![], samples: 0 })
+    }
+
+    /// Writes the input frame of a step along with the audio generated for it,
+    /// to be called for every step.
+    pub fn push_step(&mut self, input: &[f32; FRAME_SIZE], output: &[f32]) -> Result<()> {
+        self.pending.extend_from_slice(output);
+        for (i, &left) in input.iter().enumerate() {
+            let right = self.pending.get(i).copied().unwrap_or(0.0);
+            self.write_sample(left, right)?;
+        }
+        self.pending.drain(..FRAME_SIZE.min(self.pending.len()));
+        Ok(())
+    }
+
+    fn write_sample(&mut self, left: f32, right: f32) -> Result<()> {
+        self.writer.write_sample(dither_f32(left, 16, &mut self.rng) as i16)?;
+        self.writer.write_sample(dither_f32(right, 16, &mut self.rng) as i16)?;
+        self.samples += 1;
+        Ok(())
+    }
+
+    /// Writes the generated audio that outlasts the input, returns the number
+    /// of samples per channel.
+    pub fn finish(mut self) -> Result<usize> {
+        for right in std::mem::take(&mut self.pending) {
+            self.write_sample(0.0, right)?;
+        }
+        self.writer.finalize()?;
+        Ok(self.samples)
+    }
+}
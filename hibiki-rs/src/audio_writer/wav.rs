@@ -0,0 +1,143 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{bail, Result};
+use std::path::Path;
+
+use super::dither_f32;
+use crate::stream::resampler::{OutputResampler, TARGET_SAMPLE_RATE};
+
+/// Encoding of the WAV samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavSampleFormat {
+    /// 16-bit integers, TPDF dithered.
+    #[default]
+    I16,
+    /// 24-bit integers, TPDF dithered.
+    I24,
+    /// 32-bit floats, written as is.
+    F32,
+}
+
+impl std::str::FromStr for WavSampleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "i16" | "s16" => Ok(Self::I16),
+            "i24" | "s24" => Ok(Self::I24),
+            "f32" => Ok(Self::F32),
+            _ => bail!("unknown wav sample format {s}, expected i16, i24 or f32"),
+        }
+    }
+}
+
+impl std::fmt::Display for WavSampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::I16 => write!(f, "i16"),
+            Self::I24 => write!(f, "i24"),
+            Self::F32 => write!(f, "f32"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavConfig {
+    pub sample_format: WavSampleFormat,
+    /// Rate of the file, the 24kHz audio is resampled when it differs.
+    pub sample_rate: u32,
+}
+
+impl Default for WavConfig {
+    fn default() -> Self {
+        Self { sample_format: WavSampleFormat::I16, sample_rate: TARGET_SAMPLE_RATE as u32 }
+    }
+}
+
+impl WavConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(8_000..=192_000).contains(&self.sample_rate) {
+            bail!("wav sample rate must be between 8000 and 192000, got {}", self.sample_rate)
+        }
+        Ok(())
+    }
+}
+
+/// Mono PCM WAV, 24kHz 16-bit by default.
+pub struct WavWriter {
+    writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    sample_format: WavSampleFormat,
+    resampler: Option<OutputResampler>,
+    rng: u32,
+    /// Samples written at 24kHz.
+    samples: usize,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, config: &WavConfig) -> Result<Self> {
+        config.validate()?;
+        let (bits_per_sample, sample_format) = match config.sample_format {
+            WavSampleFormat::I16 => (16, hound::SampleFormat::Int),
+            WavSampleFormat::I24 => (24, hound::SampleFormat::Int),
+            WavSampleFormat::F32 => (32, hound::SampleFormat::Float),
+        };
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: config.sample_rate,
+            bits_per_sample,
+            sample_format,
+        };
+        let resampler = if config.sample_rate as usize == TARGET_SAMPLE_RATE {
+            None
+        } else {
+            Some(OutputResampler::new(config.sample_rate as usize)?)
+        };
+        let writer = hound::WavWriter::create(path, spec)?;
+        Ok(Self {
+            writer,
+            sample_format: config.sample_format,
+            resampler,
+            // Seed for dither
+            rng: 0x12345678,
+            samples: 0,
+        })
+    }
+
+    pub fn write(&mut self, pcm: &[f32]) -> Result<()> {
+        self.samples += pcm.len();
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let resampled = resampler.push_samples(pcm)?;
+                self.write_samples(&resampled)
+            }
+            None => self.write_samples(pcm),
+        }
+    }
+
+    fn write_samples(&mut self, pcm: &[f32]) -> Result<()> {
+        for &sample in pcm {
+            match self.sample_format {
+                WavSampleFormat::I16 => {
+                    self.writer.write_sample(dither_f32(sample, 16, &mut self.rng) as i16)?
+                }
+                WavSampleFormat::I24 => {
+                    self.writer.write_sample(dither_f32(sample, 24, &mut self.rng))?
+                }
+                WavSampleFormat::F32 => self.writer.write_sample(sample)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the file, returns the number of 24kHz samples written.
+    pub fn finish(mut self) -> Result<usize> {
+        if let Some(mut resampler) = self.resampler.take() {
+            let rest = resampler.finish()?;
+            self.write_samples(&rest)?;
+        }
+        self.writer.finalize()?;
+        Ok(self.samples)
+    }
+}
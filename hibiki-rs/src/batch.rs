@@ -0,0 +1,160 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::audio_writer::OutputConfig;
+use crate::gen::translate_file;
+use crate::stream::resampler::{InputChannel, FRAME_SIZE, TARGET_SAMPLE_RATE};
+use crate::stream::FrameProcessor;
+
+/// One input/output pair of a batch manifest.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ManifestItem {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Per-item record appended to the results file.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ItemResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub ok: bool,
+    /// Duration of the input audio in seconds, including the end padding.
+    pub duration_s: Option<f32>,
+    pub processing_s: Option<f32>,
+    pub ms_per_token: Option<f32>,
+    pub transcript: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct Args {
+    pub manifest: PathBuf,
+    pub results: PathBuf,
+    /// Translate items again even when their output file already exists.
+    pub overwrite: bool,
+}
+
+/// Reads a manifest of input/output pairs.
+///
+/// Files ending in `.csv` need an `input,output` header, anything else is
+/// read as JSON lines of `{"input": ..., "output": ...}` objects.
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestItem>> {
+    let is_csv = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        let mut reader = csv::Reader::from_path(path)?;
+        let mut items = vec![];
+        for (idx, item) in reader.deserialize().enumerate() {
+            let item =
+                item.with_context(|| format!("{}: invalid row {}", path.display(), idx + 1))?;
+            items.push(item)
+        }
+        Ok(items)
+    } else {
+        let content = std::fs::read_to_string(path)?;
+        let mut items = vec![];
+        for (idx, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let item = serde_json::from_str(line).with_context(|| {
+                format!("{}:{}: invalid manifest line", path.display(), idx + 1)
+            })?;
+            items.push(item)
+        }
+        Ok(items)
+    }
+}
+
+fn translate_item<P: FrameProcessor>(model: &mut P, item: &ManifestItem) -> Result<ItemResult> {
+    // Write to a temporary file first so that an interrupted run never leaves
+    // a truncated output that would be skipped on the next run.
+    let mut partial = item.output.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    // The format is picked from the final name, not the temporary one.
+    let output = OutputConfig {
+        format: Some(OutputConfig::default().format_for(&item.output)),
+        ..Default::default()
+    };
+    let channel = InputChannel::Mix;
+    let translation = translate_file(model, &item.input, channel, &partial, &output, |_| Ok(()))
+        .and_then(|translation| {
+            std::fs::rename(&partial, &item.output)?;
+            Ok(translation)
+        });
+    let translation = match translation {
+        Ok(translation) => translation,
+        Err(err) => {
+            // Nothing resumes from the partial output, do not let them pile up.
+            let _ = std::fs::remove_file(&partial);
+            return Err(err);
+        }
+    };
+    Ok(ItemResult {
+        input: item.input.clone(),
+        output: item.output.clone(),
+        ok: true,
+        duration_s: Some((translation.steps * FRAME_SIZE) as f32 / TARGET_SAMPLE_RATE as f32),
+        processing_s: Some(translation.elapsed.as_secs_f32()),
+        ms_per_token: Some(translation.ms_per_step()),
+        transcript: Some(translation.transcript),
+        error: None,
+    })
+}
+
+/// Translates the items of the manifest with `model`, skipping the ones whose
+/// output exists unless `overwrite` is set, and appends a record per item to
+/// the results file. A failed item is recorded and the next ones still run.
+pub fn run<P: FrameProcessor>(args: &Args, mut model: P) -> Result<()> {
+    let items = read_manifest(&args.manifest)?;
+    tracing::info!(items = items.len(), manifest = ?args.manifest, "loaded the manifest");
+
+    let mut results = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.results)
+        .with_context(|| format!("cannot open results file {}", args.results.display()))?;
+
+    let (mut translated, mut failed, mut skipped) = (0, 0, 0);
+    for (idx, item) in items.iter().enumerate() {
+        if !args.overwrite && item.output.exists() {
+            tracing::info!(input = ?item.input, "[{}/{}] output exists, skipping", idx + 1, items.len());
+            skipped += 1;
+            continue;
+        }
+        tracing::info!(input = ?item.input, "[{}/{}] translating", idx + 1, items.len());
+        // Every item starts from a fresh state so that its output does not
+        // depend on what was translated before.
+        model.reset();
+        let result = match translate_item(&mut model, item) {
+            Ok(result) => {
+                translated += 1;
+                result
+            }
+            Err(err) => {
+                tracing::error!(input = ?item.input, "translation failed: {err:#}");
+                failed += 1;
+                ItemResult {
+                    input: item.input.clone(),
+                    output: item.output.clone(),
+                    ok: false,
+                    duration_s: None,
+                    processing_s: None,
+                    ms_per_token: None,
+                    transcript: None,
+                    error: Some(format!("{err:#}")),
+                }
+            }
+        };
+        serde_json::to_writer(&mut results, &result)?;
+        writeln!(results)?;
+        results.flush()?;
+    }
+    tracing::info!(translated, failed, skipped, results = ?args.results, "batch done");
+    Ok(())
+}
@@ -0,0 +1,223 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::sampling::SamplingConfig;
+use crate::stream::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+use crate::ModelStats;
+
+/// Describes the model behind a session, reported in [`Event::SessionStart`].
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub lm_model_file: Option<PathBuf>,
+    pub mimi_model_file: Option<PathBuf>,
+    pub text_tokenizer: Option<PathBuf>,
+    pub sampling: Option<SamplingConfig>,
+    pub cfg_alpha: Option<f64>,
+    pub device: Option<String>,
+}
+
+impl ModelInfo {
+    pub fn named(name: &str) -> Self {
+        Self { name: name.to_string(), ..Default::default() }
+    }
+}
+
+/// One line of the `--events` output.
+///
+/// Steps are indexes of 80ms input frames since the start of the session and
+/// sample offsets refer to the 24kHz generated audio.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    SessionStart {
+        mode: String,
+        model: Box<ModelInfo>,
+        sample_rate: usize,
+        frame_size: usize,
+        unix_time_s: f64,
+    },
+    Text {
+        step: usize,
+        /// Text token id, only known for the actual model.
+        token: Option<u32>,
+        piece: String,
+        /// Number of samples generated up to and including this step.
+        sample: usize,
+    },
+    Audio {
+        step: usize,
+        offset: usize,
+        length: usize,
+    },
+    Frame {
+        step: usize,
+        processing_ms: f32,
+    },
+    /// The speaker buffer ran dry, `count` is the total for the session.
+    Underrun {
+        count: u64,
+    },
+    /// The speaker buffer was full and dropped audio, `count` is the total for
+    /// the session.
+    Overflow {
+        count: u64,
+    },
+    /// An audio device stopped working, `direction` being "input" or
+    /// "output".
+    DeviceLost {
+        direction: String,
+        device: String,
+    },
+    /// The device was reopened after `attempts` tries.
+    DeviceRecovered {
+        direction: String,
+        device: String,
+        attempts: u32,
+    },
+    /// The device could not be reopened: the session ends when it was the
+    /// input and goes on without speaker when it was the output.
+    DeviceFailed {
+        direction: String,
+        device: String,
+        message: String,
+    },
+    /// The voice activity detection noticed the start of an utterance at
+    /// `step`, `stream` only.
+    UtteranceStart {
+        step: usize,
+    },
+    /// The utterance ended at `step`, the first quiet step after it.
+    UtteranceEnd {
+        step: usize,
+    },
+    /// The model stops running from `step` on, until the next utterance, after
+    /// applying `action` (none, flush or reset).
+    LongSilence {
+        step: usize,
+        action: String,
+    },
+    /// Something went wrong with the session, e.g. invalid input sent to the
+    /// server.
+    Error {
+        message: String,
+    },
+    SessionEnd {
+        frames: usize,
+        avg_time_ms: f32,
+        p95_time_ms: f32,
+        samples_generated: usize,
+        underruns: Option<u64>,
+        overflows: Option<u64>,
+    },
+}
+
+impl Event {
+    pub fn session_start(mode: &str, model: ModelInfo) -> Self {
+        let unix_time_s = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0., |d| d.as_secs_f64());
+        Self::SessionStart {
+            mode: mode.to_string(),
+            model: Box::new(model),
+            sample_rate: TARGET_SAMPLE_RATE,
+            frame_size: FRAME_SIZE,
+            unix_time_s,
+        }
+    }
+
+    pub fn session_end(
+        stats: &ModelStats,
+        samples_generated: usize,
+        playback: Option<(u64, u64)>,
+    ) -> Self {
+        Self::SessionEnd {
+            frames: stats.frames_processed,
+            avg_time_ms: stats.avg_time_ms,
+            p95_time_ms: stats.p95_time_ms,
+            samples_generated,
+            underruns: playback.map(|p| p.0),
+            overflows: playback.map(|p| p.1),
+        }
+    }
+}
+
+/// What the model generated for one input frame.
+pub struct FrameOutput<'a> {
+    pub step: usize,
+    /// The 24kHz frame fed to the model.
+    pub input: &'a [f32; FRAME_SIZE],
+    /// Offset of `pcm` in the generated audio.
+    pub offset: usize,
+    pub pcm: &'a [f32],
+    pub text: Option<&'a str>,
+    /// Token ids and pieces making up `text`, empty when the processor does not
+    /// expose tokens.
+    pub text_tokens: &'a [(u32, String)],
+    pub processing_time: Duration,
+}
+
+impl FrameOutput<'_> {
+    /// Number of samples generated up to and including this frame.
+    pub fn end_sample(&self) -> usize {
+        self.offset + self.pcm.len()
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        let mut events = vec![];
+        let (step, sample) = (self.step, self.end_sample());
+        if !self.text_tokens.is_empty() {
+            for (token, piece) in self.text_tokens.iter() {
+                events.push(Event::Text { step, token: Some(*token), piece: piece.clone(), sample })
+            }
+        } else if let Some(text) = self.text {
+            events.push(Event::Text { step, token: None, piece: text.to_string(), sample })
+        }
+        if !self.pcm.is_empty() {
+            events.push(Event::Audio { step, offset: self.offset, length: self.pcm.len() })
+        }
+        let processing_ms = self.processing_time.as_secs_f32() * 1000.;
+        events.push(Event::Frame { step, processing_ms });
+        events
+    }
+}
+
+/// Returns true if `path` designates stdout rather than a file.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Writes events as JSON lines, flushing after each one so that consumers see
+/// them as they happen.
+pub struct EventWriter {
+    out: Box<dyn Write + Send>,
+}
+
+impl EventWriter {
+    /// Creates the events file, `-` writes to stdout.
+    pub fn create(path: &Path) -> Result<Self> {
+        let out: Box<dyn Write + Send> = if is_stdout(path) {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::io::BufWriter::new(std::fs::File::create(path)?))
+        };
+        Ok(Self::new(out))
+    }
+
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self { out }
+    }
+
+    pub fn emit(&mut self, event: &Event) -> Result<()> {
+        serde_json::to_writer(&mut self.out, event)?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
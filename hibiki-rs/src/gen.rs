@@ -1,5 +1,15 @@
 use anyhow::Result;
-use candle::{Device, IndexOp, Tensor};
+use candle::Device;
+use std::path::Path;
+
+use crate::audio_writer::{self, OutputConfig, StereoWriter};
+use crate::events::{self, Event, EventWriter, FrameOutput};
+use crate::sampling::SamplingConfig;
+use crate::stream::input::file_frames;
+use crate::stream::model::StreamingModel;
+use crate::stream::resampler::{InputChannel, FRAME_SIZE};
+use crate::stream::FrameProcessor;
+use crate::subtitles::SubtitleWriter;
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Config {
@@ -7,6 +17,9 @@ pub struct Config {
     pub moshi_name: String,
     pub tokenizer_name: String,
     pub model: moshi::lm::Config,
+    /// Recommended sampling for this model, overridable from the command line.
+    #[serde(default)]
+    pub sampling: SamplingConfig,
 }
 
 pub struct Args {
@@ -14,171 +27,168 @@ pub struct Args {
     pub lm_model_file: std::path::PathBuf,
     pub mimi_model_file: std::path::PathBuf,
     pub audio_input_file: std::path::PathBuf,
+    pub input_channel: InputChannel,
     pub text_tokenizer: std::path::PathBuf,
     pub audio_output_file: std::path::PathBuf,
-    pub seed: u64,
+    pub output: OutputConfig,
+    pub save_stereo: Option<std::path::PathBuf>,
+    pub subtitles: Option<std::path::PathBuf>,
+    pub events: Option<std::path::PathBuf>,
+    pub sampling: SamplingConfig,
     pub cfg_alpha: Option<f64>,
 }
 
-fn text(
-    text_tokenizer: &sentencepiece::SentencePieceProcessor,
-    prev_text_token: u32,
-    text_token: u32,
-    text_start_token: u32,
-) -> Option<String> {
-    if prev_text_token == text_start_token {
-        text_tokenizer.decode_piece_ids(&[text_token]).ok()
-    } else {
-        let prev_ids = text_tokenizer.decode_piece_ids(&[prev_text_token]).ok();
-        let ids = text_tokenizer.decode_piece_ids(&[prev_text_token, text_token]).ok();
-        prev_ids.and_then(|prev_ids| {
-            ids.map(|ids| {
-                if ids.len() > prev_ids.len() {
-                    ids[prev_ids.len()..].to_string()
-                } else {
-                    String::new()
-                }
-            })
-        })
-    }
+/// Summary of a file translated by [`translate_file`].
+pub struct Translation {
+    pub steps: usize,
+    pub elapsed: std::time::Duration,
+    pub transcript: String,
+    pub output_samples: usize,
 }
 
-pub fn run(args: &Args, dev: &Device) -> Result<()> {
-    let dtype = dev.bf16_default_to_f32();
-    let lm_config = &args.lm_config;
-    tracing::info!(?dtype, ?dev);
+impl Translation {
+    pub fn ms_per_step(&self) -> f32 {
+        self.elapsed.as_secs_f32() * 1000. / self.steps.max(1) as f32
+    }
+}
 
+/// Translates `input` into `output` using an already loaded model, the
+/// input channels being mixed or selected by `channel` and the output format
+/// picked by `output_config`.
+///
+/// The model is expected to be in a fresh state, `on_frame` is called with
+/// what was generated for each input frame as soon as it is available.
+pub fn translate_file<P: FrameProcessor>(
+    model: &mut P,
+    input: &Path,
+    channel: InputChannel,
+    output: &Path,
+    output_config: &OutputConfig,
+    on_frame: impl FnMut(&FrameOutput) -> Result<()>,
+) -> Result<Translation> {
     tracing::info!("loading the audio input");
-    let (in_pcm, in_pcm_len) = {
-        let (mut pcm, sample_rate) = crate::audio_io::pcm_decode(&args.audio_input_file)?;
-        pcm.extend_from_slice(&vec![0.0; 12000]);
-        let pcm = if sample_rate != 24_000 {
-            crate::audio_io::resample(&pcm, sample_rate as usize, 24_000)?
-        } else {
-            pcm
-        };
-        let pcm_len = pcm.len();
-        let pcm = Tensor::from_vec(pcm, (1, 1, pcm_len), dev)?;
-        (pcm, pcm_len)
+    let frames = file_frames(input, channel)?;
+    tracing::info!(frames = frames.len(), "loaded the audio input");
+
+    let (translation, out_pcm) = translate_frames(model, &frames, on_frame)?;
+    audio_writer::write_pcm(output, &out_pcm, output_config)?;
+    Ok(translation)
+}
+
+/// Translates 80ms frames of 24kHz audio, returning the generated audio along
+/// with the summary.
+///
+/// As for [`translate_file`], the model is expected to be in a fresh state.
+pub fn translate_frames<P: FrameProcessor>(
+    model: &mut P,
+    frames: &[[f32; FRAME_SIZE]],
+    mut on_frame: impl FnMut(&FrameOutput) -> Result<()>,
+) -> Result<(Translation, Vec<f32>)> {
+    let mut out_pcm = vec![];
+    let mut transcript = String::new();
+    tracing::info!("starting the inference loop");
+    let start_time = std::time::Instant::now();
+    for (step, frame) in frames.iter().enumerate() {
+        let frame_start = std::time::Instant::now();
+        let (pcm, text) = model.process_frame(frame)?;
+        on_frame(&FrameOutput {
+            step,
+            input: frame,
+            offset: out_pcm.len(),
+            pcm: &pcm,
+            text: text.as_deref(),
+            text_tokens: model.last_text_tokens(),
+            processing_time: frame_start.elapsed(),
+        })?;
+        out_pcm.extend_from_slice(&pcm);
+        if let Some(text) = text {
+            transcript.push_str(&text);
+        }
+    }
+    let translation = Translation {
+        steps: frames.len(),
+        elapsed: start_time.elapsed(),
+        transcript,
+        output_samples: out_pcm.len(),
     };
-    tracing::info!(in_pcm_len, "loaded the audio input");
+    Ok((translation, out_pcm))
+}
 
-    tracing::info!("loading the lm");
-    let lm_model = moshi::lm::load_lm_model(lm_config.clone(), &args.lm_model_file, dtype, dev)?;
-    tracing::info!("loading the audio tokenizer");
-    let mut mimi = moshi::mimi::load(
-        args.mimi_model_file.to_str().unwrap(),
-        Some(lm_model.generated_audio_codebooks()),
+pub fn run(args: &Args, dev: &Device) -> Result<()> {
+    tracing::info!(?dev);
+    args.output.validate()?;
+    let mut model = StreamingModel::new(
+        &args.lm_config,
+        &args.lm_model_file,
+        &args.mimi_model_file,
+        &args.text_tokenizer,
+        &args.sampling,
+        args.cfg_alpha,
         dev,
     )?;
-    tracing::info!("loading the text tokenizer");
-    let text_tokenizer = sentencepiece::SentencePieceProcessor::open(&args.text_tokenizer)?;
-    tracing::info!("done loading models");
-
-    let audio_lp = candle_transformers::generation::LogitsProcessor::from_sampling(
-        args.seed,
-        candle_transformers::generation::Sampling::TopK { k: 250, temperature: 0.8 },
-    );
-    let text_lp = candle_transformers::generation::LogitsProcessor::from_sampling(
-        args.seed,
-        candle_transformers::generation::Sampling::TopK { k: 25, temperature: 0.8 },
-    );
-    let generated_audio_codebooks = lm_config.depformer.as_ref().map_or(8, |v| v.num_slices);
 
-    let conditions = match lm_model.condition_provider() {
+    let mut subtitles = match args.subtitles.as_ref() {
+        Some(path) => Some(SubtitleWriter::create(path)?),
         None => None,
-        Some(cp) => {
-            let conditions = if args.cfg_alpha.is_some() {
-                use moshi::conditioner::Condition::AddToInput;
-                let AddToInput(c1) = cp.condition_lut("description", "very_good")?;
-                let AddToInput(c2) = cp.condition_lut("description", "very_bad")?;
-                AddToInput(Tensor::cat(&[c1, c2], 0)?)
-            } else {
-                cp.condition_lut("description", "very_good")?
-            };
-            tracing::info!(?conditions, "generated conditions");
-            Some(conditions)
-        }
     };
-    let max_steps = (in_pcm_len / 1920).min(2500);
-    let cfg_alpha = if args.cfg_alpha == Some(1.) { None } else { args.cfg_alpha };
-    let mut state = {
-        let config = moshi::lm_generate_multistream::Config {
-            acoustic_delay: 2,
-            audio_vocab_size: lm_config.audio_vocab_size,
-            generated_audio_codebooks,
-            input_audio_codebooks: lm_config.audio_codebooks - generated_audio_codebooks,
-            text_start_token: lm_config.text_out_vocab_size as u32,
-            text_eop_token: 0,
-            text_pad_token: 3,
-        };
-        moshi::lm_generate_multistream::State::new(
-            lm_model,
-            max_steps + 20,
-            audio_lp,
-            text_lp,
-            None,
-            None,
-            cfg_alpha,
-            config,
-        )
+    let mut events = match args.events.as_ref() {
+        Some(path) => Some(EventWriter::create(path)?),
+        None => None,
     };
-
-    let text_start_token = state.config().text_start_token;
-    let mut prev_text_token = text_start_token;
-    let mut out_pcms = vec![];
-    let mut text_tokens = vec![];
-    let mut nsteps = 0;
-    tracing::info!("starting the inference loop");
-    let start_time = std::time::Instant::now();
-    for start_index in 0..max_steps {
-        nsteps += 1;
-        let in_pcm = in_pcm.i((.., .., start_index * 1920..(start_index + 1) * 1920))?;
-        let codes = mimi.encode_step(&in_pcm.into())?;
-        if let Some(codes) = codes.as_option() {
-            let (_b, _codebooks, steps) = codes.dims3()?;
-            for step in 0..steps {
-                let codes = codes.i((.., .., step..step + 1))?;
-                let codes = codes.i((0, .., 0))?.to_vec1::<u32>()?;
-                let text_token =
-                    state.step_(Some(prev_text_token), &codes, None, None, conditions.as_ref())?;
-                if text_token != 0 && text_token != 3 {
-                    text_tokens.push(text_token);
-                    if let Some(text) =
-                        text(&text_tokenizer, prev_text_token, text_token, text_start_token)
-                    {
-                        use std::io::Write;
-                        print!("{text}");
-                        std::io::stdout().flush().unwrap();
-                    }
-                }
-                prev_text_token = text_token;
-                if let Some(audio_tokens) = state.last_audio_tokens() {
-                    let audio_tokens =
-                        Tensor::new(&audio_tokens[..generated_audio_codebooks], dev)?
-                            .reshape((1, 1, ()))?
-                            .t()?;
-                    let out_pcm = mimi.decode_step(&audio_tokens.into())?;
-                    if let Some(out_pcm) = out_pcm.as_option() {
-                        out_pcms.push(out_pcm.clone());
-                    }
-                }
+    let mut stereo = match args.save_stereo.as_ref() {
+        Some(path) => Some(StereoWriter::create(path)?),
+        None => None,
+    };
+    // Keep stdout clean when the events are written there.
+    let print_text = !args.events.as_deref().is_some_and(events::is_stdout);
+    if let Some(events) = events.as_mut() {
+        events.emit(&Event::session_start("gen", model.info()))?;
+    }
+    let (input, output) = (&args.audio_input_file, &args.audio_output_file);
+    let channel = args.input_channel;
+    let translation = translate_file(&mut model, input, channel, output, &args.output, |frame| {
+        if let Some(text) = frame.text {
+            if print_text {
+                use std::io::Write;
+                print!("{text}");
+                std::io::stdout().flush()?;
+            }
+            if let Some(writer) = subtitles.as_mut() {
+                writer.push(frame.end_sample(), text)?;
+            }
+        }
+        if let Some(events) = events.as_mut() {
+            for event in frame.events() {
+                events.emit(&event)?;
             }
         }
+        if let Some(stereo) = stereo.as_mut() {
+            stereo.push_step(frame.input, frame.pcm)?;
+        }
+        Ok(())
+    })?;
+    if print_text {
+        println!();
+    }
+    if let Some(events) = events.as_mut() {
+        events.emit(&Event::session_end(&model.get_stats(), translation.output_samples, None))?;
+    }
+    if let Some(writer) = subtitles {
+        writer.finish()?;
+        tracing::info!(subtitles = ?args.subtitles, "generated subtitles");
+    }
+    if let Some(stereo) = stereo {
+        stereo.finish()?;
+        tracing::info!(stereo = ?args.save_stereo, "generated stereo recording");
     }
-    println!();
-    let dt = start_time.elapsed().as_secs_f32();
     tracing::info!(
-        "generated {nsteps} steps in {dt:.2}s, {:.0}ms/token",
-        dt * 1000. / (nsteps as f32)
+        "generated {} steps in {:.2}s, {:.0}ms/token",
+        translation.steps,
+        translation.elapsed.as_secs_f32(),
+        translation.ms_per_step()
     );
-    let str = text_tokenizer.decode_piece_ids(&text_tokens)?;
-    tracing::info!(str, "generated text");
-    let out_pcms = Tensor::cat(&out_pcms, 2)?;
-    tracing::info!(shape = ?out_pcms.shape(), "generated audio");
-    let out_pcms = out_pcms.i((0, 0))?.to_vec1::<f32>()?;
-    let mut out_wav = std::fs::File::create(&args.audio_output_file)?;
-    moshi::wav::write_pcm_as_wav(&mut out_wav, &out_pcms, 24_000)?;
+    tracing::info!(str = translation.transcript, "generated text");
+    tracing::info!(samples = translation.output_samples, "generated audio");
     tracing::info!(audio = ?args.audio_output_file, "generated audio");
     Ok(())
 }
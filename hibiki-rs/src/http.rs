@@ -0,0 +1,415 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Local HTTP API translating whole files.
+//!
+//! - `POST /translate` takes an audio file in any format symphonia decodes and
+//!   responds with the translated WAV, the job id being in the `X-Job-Id`
+//!   header and the transcript in the `X-Transcript` one (percent-encoded, cut
+//!   past `MAX_TRANSCRIPT_HEADER_BYTES`). With `?async=true`, or when the input
+//!   is longer than the sync limit, it responds with a job instead.
+//! - `GET /jobs/<id>` returns the status of a job, and its transcript once done.
+//! - `GET /jobs/<id>/audio` returns the translated WAV of a finished job.
+//!
+//! A single worker owns the model and translates the jobs in order, requests
+//! arriving while it is busy wait in the queue. A fixed pool of threads handles
+//! the requests, the ones waiting for their translation included, and the
+//! queue holds a fixed number of jobs, past which requests get a 503. This
+//! bounds the memory taken by the request bodies and the queued audio.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request};
+
+use crate::gen::translate_frames;
+use crate::stream::input::{pcm_frames, AudioFrame};
+use crate::stream::resampler::{InputChannel, TARGET_SAMPLE_RATE};
+use crate::stream::FrameProcessor;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8999";
+/// Longer inputs get a job id rather than waiting for the translation.
+pub const DEFAULT_MAX_SYNC_SECONDS: f32 = 60.;
+/// How often the server checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_BODY_BYTES: u64 = 512 << 20;
+/// Longer transcripts are cut in the `X-Transcript` header, which is then
+/// followed by `X-Transcript-Truncated: true`, the job has the whole text.
+const MAX_TRANSCRIPT_HEADER_BYTES: usize = 32 << 10;
+/// Jobs waiting for the worker, each holding its decoded audio.
+const MAX_QUEUED_JOBS: usize = 16;
+/// Requests handled at the same time, the next ones wait for a free thread.
+const REQUEST_THREADS: usize = 8;
+/// Finished jobs kept around for their results, the oldest ones are dropped
+/// first.
+const MAX_FINISHED_JOBS: usize = 64;
+
+type Response = tiny_http::Response<Cursor<Vec<u8>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed)
+    }
+}
+
+/// What `GET /jobs/<id>` returns.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub status: JobStatus,
+    /// Duration of the input audio in seconds.
+    pub duration_s: f32,
+    /// Jobs to be translated before this one, for queued jobs.
+    pub queue_position: Option<usize>,
+    pub transcript: Option<String>,
+    pub ms_per_token: Option<f32>,
+    pub error: Option<String>,
+}
+
+struct Job {
+    info: JobInfo,
+    /// The translated audio as a WAV file.
+    wav: Option<Vec<u8>>,
+    /// Requests waiting for the result, the job is kept until they got it.
+    waiters: usize,
+}
+
+#[derive(Default)]
+struct Jobs {
+    jobs: BTreeMap<u64, Job>,
+    next_id: u64,
+}
+
+impl Jobs {
+    fn info(&self, id: u64) -> Option<JobInfo> {
+        let job = self.jobs.get(&id)?;
+        let mut info = job.info.clone();
+        if info.status == JobStatus::Queued {
+            let ahead = self.jobs.range(..id).filter(|(_, j)| !j.info.status.is_finished()).count();
+            info.queue_position = Some(ahead);
+        }
+        Some(info)
+    }
+
+    fn drop_old_jobs(&mut self) {
+        let finished: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|(_, j)| j.info.status.is_finished() && j.waiters == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_JOBS)) {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+/// State shared between the request handlers and the worker.
+struct State {
+    jobs: Mutex<Jobs>,
+    /// Notified whenever a job finishes.
+    finished: Condvar,
+    queue: mpsc::SyncSender<(u64, Vec<AudioFrame>)>,
+    max_sync_seconds: f32,
+}
+
+pub struct Args {
+    pub addr: String,
+    pub max_sync_seconds: f32,
+}
+
+/// Serves until Ctrl-C is pressed.
+pub fn run<P: FrameProcessor + Send + 'static>(args: &Args, processor: P) -> Result<()> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_ctrlc = shutdown.clone();
+    ctrlc::set_handler(move || {
+        tracing::info!("Shutdown signal received");
+        shutdown_ctrlc.store(true, Ordering::Relaxed);
+    })?;
+
+    let listener = TcpListener::bind(&args.addr)?;
+    tracing::info!(addr = %listener.local_addr()?, "listening");
+    serve(listener, processor, args.max_sync_seconds, shutdown)
+}
+
+/// Handles requests on `listener` until `shutdown` is set, translating with
+/// `processor` on a worker thread.
+pub fn serve<P: FrameProcessor + Send + 'static>(
+    listener: TcpListener,
+    processor: P,
+    max_sync_seconds: f32,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let server = tiny_http::Server::from_listener(listener, None)
+        .map_err(|e| anyhow::anyhow!("cannot start the server: {e}"))?;
+    let (queue, queue_rx) = mpsc::sync_channel(MAX_QUEUED_JOBS);
+    let state = Arc::new(State {
+        jobs: Mutex::new(Jobs::default()),
+        finished: Condvar::new(),
+        queue,
+        max_sync_seconds,
+    });
+    let worker_state = state.clone();
+    let worker_shutdown = shutdown.clone();
+    let worker = std::thread::Builder::new()
+        .name("translate".to_string())
+        .spawn(move || run_worker(processor, queue_rx, &worker_state, &worker_shutdown))?;
+
+    let server = Arc::new(server);
+    let handlers = (0..REQUEST_THREADS)
+        .map(|_| {
+            let (server, state, shutdown) = (server.clone(), state.clone(), shutdown.clone());
+            std::thread::Builder::new()
+                .name("request".to_string())
+                .spawn(move || handle_requests(&server, &state, &shutdown))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    // The worker stops on shutdown.
+    let _ = worker.join();
+    // Requests still waiting for their translation get an error.
+    {
+        let mut jobs = lock(&state);
+        for job in jobs.jobs.values_mut().filter(|j| !j.info.status.is_finished()) {
+            job.info.status = JobStatus::Failed;
+            job.info.error = Some("the server shut down".to_string());
+        }
+        state.finished.notify_all();
+    }
+    for handler in handlers {
+        let _ = handler.join();
+    }
+    Ok(())
+}
+
+/// Runs on each thread of the pool until `shutdown` is set.
+fn handle_requests(server: &tiny_http::Server, state: &State, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::Relaxed) {
+        let request = match server.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::error!("cannot receive requests: {e}");
+                shutdown.store(true, Ordering::Relaxed);
+                break;
+            }
+        };
+        let (method, url) = (request.method().clone(), request.url().to_string());
+        if let Err(e) = handle_request(request, state) {
+            tracing::warn!(%method, url, "cannot respond: {e}")
+        }
+    }
+}
+
+fn run_worker<P: FrameProcessor>(
+    mut processor: P,
+    queue: mpsc::Receiver<(u64, Vec<AudioFrame>)>,
+    state: &State,
+    shutdown: &AtomicBool,
+) {
+    while !shutdown.load(Ordering::Relaxed) {
+        let (id, frames) = match queue.recv_timeout(POLL_INTERVAL) {
+            Ok(job) => job,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(job) = state.jobs.lock().unwrap().jobs.get_mut(&id) {
+            job.info.status = JobStatus::Running;
+        }
+        tracing::info!(id, frames = frames.len(), "translating");
+        processor.reset();
+        let result =
+            translate_frames(&mut processor, &frames, |_| Ok(())).and_then(|(translation, pcm)| {
+                let mut wav = vec![];
+                moshi::wav::write_pcm_as_wav(&mut wav, &pcm, TARGET_SAMPLE_RATE as u32)?;
+                Ok((translation, wav))
+            });
+        let mut jobs = state.jobs.lock().unwrap();
+        if let Some(job) = jobs.jobs.get_mut(&id) {
+            match result {
+                Ok((translation, wav)) => {
+                    job.info.status = JobStatus::Done;
+                    job.info.ms_per_token = Some(translation.ms_per_step());
+                    job.info.transcript = Some(translation.transcript);
+                    job.wav = Some(wav);
+                }
+                Err(e) => {
+                    tracing::warn!(id, "translation failed: {e}");
+                    job.info.status = JobStatus::Failed;
+                    job.info.error = Some(e.to_string());
+                }
+            }
+        }
+        jobs.drop_old_jobs();
+        state.finished.notify_all();
+    }
+}
+
+fn handle_request(mut request: Request, state: &State) -> std::io::Result<()> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let response = match (request.method(), segments.as_slice()) {
+        (Method::Post, ["translate"]) => {
+            let wait = !query.split('&').any(|p| p == "async=true" || p == "async=1");
+            translate(&mut request, state, wait)
+        }
+        (Method::Get, ["jobs", id]) => match id.parse().ok().and_then(|id| lock(state).info(id)) {
+            Some(info) => json_response(200, &info),
+            None => error_response(404, "unknown job"),
+        },
+        (Method::Get, ["jobs", id, "audio"]) => match id.parse() {
+            Ok(id) => job_audio(state, id),
+            Err(_) => error_response(404, "unknown job"),
+        },
+        (_, ["translate"]) | (_, ["jobs", ..]) => error_response(405, "method not allowed"),
+        _ => error_response(404, "not found"),
+    };
+    request.respond(response)
+}
+
+fn lock(state: &State) -> std::sync::MutexGuard<'_, Jobs> {
+    state.jobs.lock().unwrap()
+}
+
+fn translate(request: &mut Request, state: &State, wait: bool) -> Response {
+    let mut body = vec![];
+    if let Err(e) = request.as_reader().take(MAX_BODY_BYTES + 1).read_to_end(&mut body) {
+        return error_response(400, &format!("cannot read the body: {e}"));
+    }
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return error_response(413, "audio file too large");
+    }
+    let (pcm, sample_rate) = match crate::audio_io::pcm_decode_bytes(body, InputChannel::Mix) {
+        Ok(decoded) => decoded,
+        Err(e) => return error_response(400, &format!("cannot decode the audio: {e}")),
+    };
+    let duration_s = pcm.len() as f32 / sample_rate.max(1) as f32;
+    let frames = match pcm_frames(pcm, sample_rate) {
+        Ok(frames) => frames,
+        Err(e) => return error_response(400, &format!("cannot decode the audio: {e}")),
+    };
+
+    let wait = wait && duration_s <= state.max_sync_seconds;
+    let id = {
+        let mut jobs = lock(state);
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        let info = JobInfo {
+            id,
+            status: JobStatus::Queued,
+            duration_s,
+            queue_position: None,
+            transcript: None,
+            ms_per_token: None,
+            error: None,
+        };
+        jobs.jobs.insert(id, Job { info, wav: None, waiters: usize::from(wait) });
+        id
+    };
+    if let Err(e) = state.queue.try_send((id, frames)) {
+        lock(state).jobs.remove(&id);
+        return match e {
+            mpsc::TrySendError::Full(_) => error_response(503, "too many queued jobs"),
+            mpsc::TrySendError::Disconnected(_) => {
+                error_response(503, "the server is shutting down")
+            }
+        };
+    }
+    tracing::info!(id, duration_s, "queued");
+
+    if !wait {
+        let info = lock(state).info(id);
+        let location = Header::from_bytes("Location", format!("/jobs/{id}")).unwrap();
+        return json_response(202, &info).with_header(location);
+    }
+    let mut jobs = state
+        .finished
+        .wait_while(lock(state), |jobs| {
+            jobs.jobs.get(&id).is_some_and(|j| !j.info.status.is_finished())
+        })
+        .unwrap();
+    match jobs.jobs.get_mut(&id) {
+        Some(job) => {
+            job.waiters -= 1;
+            let transcript = job.info.transcript.as_deref().unwrap_or_default();
+            let (encoded, truncated) = percent_encode(transcript, MAX_TRANSCRIPT_HEADER_BYTES);
+            match &job.wav {
+                Some(wav) => {
+                    let response = wav_response(wav.clone())
+                        .with_header(Header::from_bytes("X-Job-Id", id.to_string()).unwrap())
+                        .with_header(Header::from_bytes("X-Transcript", encoded).unwrap());
+                    if truncated {
+                        response.with_header(
+                            Header::from_bytes("X-Transcript-Truncated", "true").unwrap(),
+                        )
+                    } else {
+                        response
+                    }
+                }
+                None => json_response(500, &job.info),
+            }
+        }
+        None => error_response(500, "the job expired"),
+    }
+}
+
+fn job_audio(state: &State, id: u64) -> Response {
+    let jobs = lock(state);
+    match jobs.jobs.get(&id) {
+        Some(Job { wav: Some(wav), .. }) => wav_response(wav.clone()),
+        Some(_) => json_response(409, &jobs.info(id)),
+        None => error_response(404, "unknown job"),
+    }
+}
+
+fn wav_response(wav: Vec<u8>) -> Response {
+    Response::from_data(wav).with_header(Header::from_bytes("Content-Type", "audio/wav").unwrap())
+}
+
+fn json_response<T: serde::Serialize>(status: u16, body: &T) -> Response {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_response(status: u16, message: &str) -> Response {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+/// Encodes `text` so that it can be used as a header value, up to `max_bytes`
+/// of output cut between characters. Also tells whether it was cut.
+fn percent_encode(text: &str, max_bytes: usize) -> (String, bool) {
+    let mut encoded = String::with_capacity(text.len().min(max_bytes));
+    for c in text.chars() {
+        let mut buf = [0; 4];
+        let bytes = c.encode_utf8(&mut buf).as_bytes();
+        let len = if c.is_ascii_alphanumeric() || "-_.~".contains(c) { 1 } else { 3 * bytes.len() };
+        if encoded.len() + len > max_bytes {
+            return (encoded, true);
+        }
+        for &b in bytes {
+            if len == 1 {
+                encoded.push(b as char)
+            } else {
+                encoded.push_str(&format!("%{b:02X}"))
+            }
+        }
+    }
+    (encoded, false)
+}
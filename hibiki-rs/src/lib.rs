@@ -0,0 +1,25 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Hibiki, a real-time speech-to-speech translation model.
+//!
+//! The [`Translator`] type is the entry point for embedding the model: it
+//! consumes 80ms frames of 24kHz mono audio and returns the translated audio
+//! and text produced for each frame.
+
+pub mod audio_io;
+pub mod audio_writer;
+pub mod batch;
+pub mod events;
+pub mod gen;
+pub mod http;
+pub mod sampling;
+pub mod serve;
+pub mod stream;
+pub mod subtitles;
+mod translator;
+
+pub use stream::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+pub use stream::{FrameProcessor, ModelStats};
+pub use translator::{ModelFiles, Translator, TranslatorOutput};
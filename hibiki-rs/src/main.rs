@@ -4,12 +4,19 @@
 
 use anyhow::Result;
 use clap::Parser;
-
-mod audio_io;
-mod gen;
-mod stream;
+use std::path::PathBuf;
 
 use candle::Device;
+use hibiki::audio_writer::{
+    FlacConfig, OpusConfig, OutputConfig, OutputFormat, WavConfig, WavSampleFormat,
+    DEFAULT_OPUS_BITRATE,
+};
+use hibiki::sampling::{SamplingConfig, SamplingOverrides};
+use hibiki::stream::vad;
+use hibiki::{batch, gen, http, serve, stream, ModelFiles, Translator};
+
+/// Latency of the dry-run echo, roughly that of the real model (2s).
+const DRY_RUN_DELAY_FRAMES: usize = 25;
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -21,6 +28,156 @@ struct Args {
     tracing: bool,
 }
 
+/// Overrides of the sampling recommended by the model config.
+#[derive(Debug, clap::Args)]
+struct SamplingArgs {
+    /// Seed for both the text and audio sampling [default: 299792458]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Seed for the text sampling, overrides --seed
+    #[arg(long)]
+    text_seed: Option<u64>,
+
+    /// Seed for the audio sampling, overrides --seed
+    #[arg(long)]
+    audio_seed: Option<u64>,
+
+    /// Text sampling temperature, 0 always picks the most likely token
+    #[arg(long)]
+    text_temperature: Option<f64>,
+
+    /// Sample text among the k most likely tokens, 0 to disable
+    #[arg(long)]
+    text_top_k: Option<usize>,
+
+    /// Nucleus sampling threshold for text, 1 to disable
+    #[arg(long)]
+    text_top_p: Option<f64>,
+
+    /// Greedy text decoding, e.g. for reproducible evaluations,
+    /// `--text-greedy=false` turns it off when the model config enables it
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    text_greedy: Option<bool>,
+
+    /// Audio sampling temperature, 0 always picks the most likely token
+    #[arg(long)]
+    audio_temperature: Option<f64>,
+
+    /// Sample audio among the k most likely tokens, 0 to disable
+    #[arg(long)]
+    audio_top_k: Option<usize>,
+
+    /// Nucleus sampling threshold for audio, 1 to disable
+    #[arg(long)]
+    audio_top_p: Option<f64>,
+
+    /// Greedy audio decoding, `--audio-greedy=false` turns it off
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    audio_greedy: Option<bool>,
+}
+
+impl SamplingArgs {
+    fn resolve(&self, config: &SamplingConfig) -> SamplingConfig {
+        let mut config = config.clone();
+        SamplingOverrides {
+            temperature: self.text_temperature,
+            top_k: self.text_top_k,
+            top_p: self.text_top_p,
+            greedy: self.text_greedy,
+            seed: self.text_seed.or(self.seed),
+        }
+        .apply(&mut config.text);
+        SamplingOverrides {
+            temperature: self.audio_temperature,
+            top_k: self.audio_top_k,
+            top_p: self.audio_top_p,
+            greedy: self.audio_greedy,
+            seed: self.audio_seed.or(self.seed),
+        }
+        .apply(&mut config.audio);
+        config
+    }
+}
+
+/// Format of the saved audio.
+#[derive(Debug, clap::Args)]
+struct OutputArgs {
+    /// Format of the saved audio (wav, opus or flac), guessed from the file
+    /// extension by default
+    #[arg(long)]
+    output_format: Option<OutputFormat>,
+
+    /// Sample format of the wav output: i16 (dithered), i24 or f32
+    #[arg(long, default_value = "i16")]
+    wav_format: WavSampleFormat,
+
+    /// Sample rate of the wav output, the 24kHz audio is resampled when it differs
+    #[arg(long, default_value_t = 24_000)]
+    wav_sample_rate: u32,
+
+    /// Opus bitrate in bits per second
+    #[arg(long, default_value_t = DEFAULT_OPUS_BITRATE)]
+    opus_bitrate: u32,
+
+    /// Rate the opus encoder runs at: 8000, 12000, 16000, 24000 or 48000
+    #[arg(long, default_value_t = 24_000)]
+    opus_sample_rate: u32,
+
+    /// Bit depth of the flac output: 16 or 24
+    #[arg(long, default_value_t = 16)]
+    flac_bits: u32,
+}
+
+impl OutputArgs {
+    fn config(&self) -> OutputConfig {
+        OutputConfig {
+            format: self.output_format,
+            wav: WavConfig { sample_format: self.wav_format, sample_rate: self.wav_sample_rate },
+            opus: OpusConfig { bitrate: self.opus_bitrate, sample_rate: self.opus_sample_rate },
+            flac: FlacConfig { bits_per_sample: self.flac_bits },
+        }
+    }
+}
+
+/// Voice activity detection of the stream command.
+#[derive(Debug, clap::Args)]
+struct VadArgs {
+    /// Detect the utterances of the input, reported in the events, and pause the
+    /// model during long silences
+    #[arg(long)]
+    vad: bool,
+
+    /// Input level above which a frame is speech, in dBFS
+    #[arg(long, default_value_t = vad::DEFAULT_THRESHOLD_DB, allow_hyphen_values = true, requires = "vad")]
+    vad_threshold: f32,
+
+    /// How long an utterance lasts past its last loud frame, in milliseconds
+    #[arg(long, default_value_t = vad::DEFAULT_HANGOVER.as_millis() as u64, requires = "vad")]
+    vad_hangover_ms: u64,
+
+    /// Time without speech in the input nor translation in the output after which
+    /// the model pauses until the next utterance, in milliseconds
+    #[arg(long, default_value_t = vad::DEFAULT_LONG_SILENCE.as_millis() as u64, requires = "vad")]
+    vad_long_silence_ms: u64,
+
+    /// What else happens at a long silence: none, flush (end the subtitle cue and
+    /// the line of transcript) or reset (also start a new model session)
+    #[arg(long, default_value = "none", requires = "vad")]
+    vad_on_long_silence: vad::SilenceAction,
+}
+
+impl VadArgs {
+    fn config(&self) -> Option<vad::VadConfig> {
+        self.vad.then(|| vad::VadConfig {
+            threshold_db: self.vad_threshold,
+            hangover: std::time::Duration::from_millis(self.vad_hangover_ms),
+            long_silence: std::time::Duration::from_millis(self.vad_long_silence_ms),
+            on_long_silence: self.vad_on_long_silence,
+        })
+    }
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum Command {
     Gen {
@@ -42,11 +199,71 @@ enum Command {
         #[arg()]
         audio_input_file: String,
 
+        /// Channel of the input file to translate, from 0, or mix to average them
+        #[arg(long, default_value = "mix")]
+        input_channel: stream::InputChannel,
+
         #[arg()]
         audio_output_file: String,
 
-        #[arg(long, default_value_t = 299_792_458)]
-        seed: u64,
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Also save a stereo WAV with the input on the left channel and the
+        /// translation on the right one, aligned by step
+        #[arg(long)]
+        save_stereo: Option<String>,
+
+        /// Also write the generated text as subtitles (.srt or .vtt)
+        #[arg(long)]
+        subtitles: Option<String>,
+
+        /// Write JSON lines events for the text, audio and timing ("-" for stdout)
+        #[arg(long)]
+        events: Option<String>,
+
+        #[command(flatten)]
+        sampling: SamplingArgs,
+
+        #[arg(long)]
+        cfg_alpha: Option<f64>,
+
+        /// Run on cpu
+        #[arg(long)]
+        cpu: bool,
+    },
+    /// Translate all the files listed in a manifest, loading the model once.
+    Batch {
+        /// JSON lines of {"input": ..., "output": ...} objects, or a csv file with an
+        /// input,output header.
+        #[arg()]
+        manifest: String,
+
+        /// Where to append per-item results, defaults to <manifest>.results.jsonl
+        #[arg(long)]
+        results: Option<String>,
+
+        /// Translate items again even if their output file already exists
+        #[arg(long)]
+        overwrite: bool,
+
+        #[arg(long)]
+        lm_model_file: Option<String>,
+
+        #[arg(long)]
+        mimi_model_file: Option<String>,
+
+        #[arg(long)]
+        config: Option<String>,
+
+        #[arg(long)]
+        text_tokenizer: Option<String>,
+
+        #[arg(long, default_value = "kyutai/hibiki-1b-rs-bf16")]
+        hf_repo: String,
+
+        #[command(flatten)]
+        sampling: SamplingArgs,
 
         #[arg(long)]
         cfg_alpha: Option<f64>,
@@ -56,30 +273,102 @@ enum Command {
         cpu: bool,
     },
     Stream {
-        /// Input audio file (mutually exclusive with --input-device)
+        /// Input audio file, "-" for raw PCM on stdin (mutually exclusive with
+        /// --input-device)
         #[arg(long, group = "input")]
         input_file: Option<String>,
 
-        /// Input device name (substring match, case-insensitive)
+        /// Feed --input-file as fast as the model consumes it instead of in real time
+        #[arg(long, requires = "input_file")]
+        no_pace: bool,
+
+        /// Sample format of the raw PCM on stdin: s16le (default) or f32le
+        #[arg(long, requires = "input_file")]
+        input_format: Option<stream::RawSampleFormat>,
+
+        /// Sample rate of the raw PCM on stdin [default: 24000]
+        #[arg(long, requires = "input_file")]
+        input_rate: Option<u32>,
+
+        /// Interleaved channels of the raw PCM on stdin [default: 1]
+        #[arg(long, requires = "input_file")]
+        input_channels: Option<usize>,
+
+        /// Channel of the file, stdin or device input to translate, from 0, or
+        /// mix to average them
+        #[arg(long, default_value = "mix")]
+        input_channel: stream::InputChannel,
+
+        /// Input device, by its index in --list-devices or by name (exact match,
+        /// else case-insensitive substring)
         #[arg(long, group = "input")]
         input_device: Option<String>,
 
-        /// Output device name (substring match, case-insensitive)
+        /// Output device, by its index in --list-devices or by name (exact match,
+        /// else case-insensitive substring)
         #[arg(long)]
         output_device: Option<String>,
 
+        /// Audio host to look the devices up in, e.g. alsa or jack (JACK needs a
+        /// build with the jack feature), the default host otherwise
+        #[arg(long)]
+        audio_host: Option<String>,
+
+        /// Fail when a device name matches several devices, or no output device,
+        /// instead of using the first match or the default output
+        #[arg(long)]
+        strict: bool,
+
         /// Disable speaker output (useful for file-to-file only)
         #[arg(long)]
         disable_speaker: bool,
 
-        /// Save generated audio to WAV file
+        /// Save generated audio to a WAV, Ogg/Opus or FLAC file
         #[arg(long)]
         save_output: Option<String>,
 
-        /// List available audio devices and exit
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Record the 24kHz mono audio fed to the model, to replay a session with
+        /// gen or --input-file (WAV, Ogg/Opus or FLAC from the extension)
+        #[arg(long)]
+        save_input: Option<String>,
+
+        /// Save a stereo WAV with the 24kHz input fed to the model on the left
+        /// channel and the translation on the right one, aligned by step
+        #[arg(long)]
+        save_stereo: Option<String>,
+
+        /// Write the generated audio to stdout as raw 24kHz mono PCM (s16le or
+        /// f32le), the logs and the text then go to stderr
+        #[arg(long)]
+        stdout_pcm: Option<stream::RawSampleFormat>,
+
+        /// Write the generated text as subtitles (.srt or .vtt), timed against the
+        /// generated audio
+        #[arg(long)]
+        subtitles: Option<String>,
+
+        /// Write JSON lines events for the text, audio and timing ("-" for stdout)
+        #[arg(long)]
+        events: Option<String>,
+
+        #[command(flatten)]
+        vad: VadArgs,
+
+        /// List available audio devices with their configs and exit
         #[arg(long)]
         list_devices: bool,
 
+        /// Print the device list as JSON
+        #[arg(long, requires = "list_devices")]
+        json: bool,
+
+        /// Replace the model with a delayed echo of the input (no weights are loaded)
+        #[arg(long)]
+        dry_run: bool,
+
         #[arg(long)]
         lm_model_file: Option<String>,
 
@@ -95,8 +384,87 @@ enum Command {
         #[arg(long, default_value = "kyutai/hibiki-1b-rs-bf16")]
         hf_repo: String,
 
-        #[arg(long, default_value_t = 299_792_458)]
-        seed: u64,
+        #[command(flatten)]
+        sampling: SamplingArgs,
+
+        #[arg(long)]
+        cfg_alpha: Option<f64>,
+
+        /// Run on cpu
+        #[arg(long)]
+        cpu: bool,
+    },
+    /// Translate audio streamed over websockets, see the README for the protocol.
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = serve::DEFAULT_ADDR)]
+        addr: String,
+
+        /// Connections served at the same time, others are rejected
+        #[arg(long, default_value_t = serve::DEFAULT_MAX_CONNECTIONS)]
+        max_connections: usize,
+
+        /// Replace the model with a delayed echo of the input (no weights are loaded)
+        #[arg(long)]
+        dry_run: bool,
+
+        #[arg(long)]
+        lm_model_file: Option<String>,
+
+        #[arg(long)]
+        mimi_model_file: Option<String>,
+
+        #[arg(long)]
+        config: Option<String>,
+
+        #[arg(long)]
+        text_tokenizer: Option<String>,
+
+        #[arg(long, default_value = "kyutai/hibiki-1b-rs-bf16")]
+        hf_repo: String,
+
+        #[command(flatten)]
+        sampling: SamplingArgs,
+
+        #[arg(long)]
+        cfg_alpha: Option<f64>,
+
+        /// Run on cpu
+        #[arg(long)]
+        cpu: bool,
+    },
+    /// Translate files posted to a local HTTP API, see the README for the endpoints.
+    Http {
+        /// Address to listen on
+        #[arg(long, default_value = http::DEFAULT_ADDR)]
+        addr: String,
+
+        /// Inputs longer than this many seconds get a job id instead of waiting
+        /// for the translation
+        #[arg(long, default_value_t = http::DEFAULT_MAX_SYNC_SECONDS)]
+        max_sync_seconds: f32,
+
+        /// Replace the model with a delayed echo of the input (no weights are loaded)
+        #[arg(long)]
+        dry_run: bool,
+
+        #[arg(long)]
+        lm_model_file: Option<String>,
+
+        #[arg(long)]
+        mimi_model_file: Option<String>,
+
+        #[arg(long)]
+        config: Option<String>,
+
+        #[arg(long)]
+        text_tokenizer: Option<String>,
+
+        #[arg(long, default_value = "kyutai/hibiki-1b-rs-bf16")]
+        hf_repo: String,
+
+        #[command(flatten)]
+        sampling: SamplingArgs,
 
         #[arg(long)]
         cfg_alpha: Option<f64>,
@@ -119,6 +487,15 @@ pub fn device(cpu: bool) -> Result<Device> {
     }
 }
 
+/// Logs go to stdout unless it is used for machine readable output.
+fn init_logging(to_stderr: bool) {
+    if to_stderr {
+        tracing_subscriber::fmt().with_writer(std::io::stderr).init()
+    } else {
+        tracing_subscriber::fmt::init()
+    }
+}
+
 fn main() -> Result<()> {
     use tracing_chrome::ChromeLayerBuilder;
     use tracing_subscriber::prelude::*;
@@ -133,127 +510,247 @@ fn main() -> Result<()> {
     };
     match args.command {
         Command::Gen {
-            seed,
+            sampling,
             text_tokenizer,
             lm_model_file,
             config,
             mimi_model_file,
             hf_repo,
             audio_input_file,
+            input_channel,
             audio_output_file,
+            output,
+            save_stereo,
+            subtitles,
+            events,
             cfg_alpha,
             cpu,
         } => {
             let dev = device(cpu)?;
-            tracing_subscriber::fmt::init();
-            let api = hf_hub::api::sync::Api::new()?;
-            let hf_repo = match hf_repo.as_str() {
-                "1b" => "kyutai/hibiki-1b-rs-bf16".to_string(),
-                "2b" => "kyutai/hibiki-2b-rs-bf16".to_string(),
-                _ => hf_repo,
-            };
-            let repo = api.model(hf_repo);
-            let config = match config {
-                None => repo.get("config.toml")?,
-                Some(f) => std::path::PathBuf::from(f),
-            };
-            tracing::info!("loading the config");
-            let config = std::fs::read_to_string(&config)?;
-            let config: gen::Config = toml::from_str(&config)?;
-
-            let lm_model_file = match lm_model_file {
-                None => repo.get(&config.moshi_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
-            let mimi_model_file = match mimi_model_file {
-                None => repo.get(&config.mimi_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
-            let text_tokenizer = match text_tokenizer {
-                None => repo.get(&config.tokenizer_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
+            init_logging(events.as_deref() == Some("-"));
+            let files = ModelFiles::resolve(
+                &hf_repo,
+                config.map(PathBuf::from),
+                lm_model_file.map(PathBuf::from),
+                mimi_model_file.map(PathBuf::from),
+                text_tokenizer.map(PathBuf::from),
+            )?;
 
             let args = gen::Args {
-                lm_config: config.model,
-                lm_model_file,
-                mimi_model_file,
-                text_tokenizer,
+                lm_config: files.config.model,
+                lm_model_file: files.lm_model_file,
+                mimi_model_file: files.mimi_model_file,
+                text_tokenizer: files.text_tokenizer,
                 audio_input_file: audio_input_file.into(),
+                input_channel,
                 audio_output_file: audio_output_file.into(),
-                seed,
+                output: output.config(),
+                save_stereo: save_stereo.map(PathBuf::from),
+                subtitles: subtitles.map(PathBuf::from),
+                events: events.map(PathBuf::from),
+                sampling: sampling.resolve(&files.config.sampling),
                 cfg_alpha,
             };
             gen::run(&args, &dev)?
         }
+        Command::Batch {
+            manifest,
+            results,
+            overwrite,
+            lm_model_file,
+            mimi_model_file,
+            config,
+            text_tokenizer,
+            hf_repo,
+            sampling,
+            cfg_alpha,
+            cpu,
+        } => {
+            let dev = device(cpu)?;
+            tracing_subscriber::fmt::init();
+            let files = ModelFiles::resolve(
+                &hf_repo,
+                config.map(PathBuf::from),
+                lm_model_file.map(PathBuf::from),
+                mimi_model_file.map(PathBuf::from),
+                text_tokenizer.map(PathBuf::from),
+            )?;
+            let results = match results {
+                Some(results) => PathBuf::from(results),
+                None => PathBuf::from(format!("{manifest}.results.jsonl")),
+            };
+            let args = batch::Args { manifest: manifest.into(), results, overwrite };
+
+            tracing::info!("Loading models...");
+            let sampling = sampling.resolve(&files.config.sampling);
+            let translator = Translator::with_sampling(&files, &sampling, cfg_alpha, &dev)?;
+            batch::run(&args, translator)?
+        }
         Command::Stream {
             input_file,
+            no_pace,
+            input_format,
+            input_rate,
+            input_channels,
+            input_channel,
             input_device,
             output_device,
+            audio_host,
+            strict,
             disable_speaker,
             save_output,
+            output,
+            save_input,
+            save_stereo,
+            stdout_pcm,
+            subtitles,
+            events,
+            vad,
             list_devices,
+            json,
+            dry_run,
             lm_model_file,
             mimi_model_file,
             config,
             text_tokenizer,
             hf_repo,
-            seed,
+            sampling,
             cfg_alpha,
             cpu,
         } => {
             // Initialize logging first
-            tracing_subscriber::fmt::init();
-            
+            init_logging(events.as_deref() == Some("-") || stdout_pcm.is_some() || json);
+
             // Handle --list-devices
             if list_devices {
-                return stream::list_devices();
+                let host = stream::find_host(audio_host.as_deref())?;
+                return stream::list_devices(host, json);
             }
-            
-            let dev = device(cpu)?;
-            let api = hf_hub::api::sync::Api::new()?;
-            let hf_repo = match hf_repo.as_str() {
-                "1b" => "kyutai/hibiki-1b-rs-bf16".to_string(),
-                "2b" => "kyutai/hibiki-2b-rs-bf16".to_string(),
-                _ => hf_repo,
-            };
-            let repo = api.model(hf_repo);
-            let config = match config {
-                None => repo.get("config.toml")?,
-                Some(f) => std::path::PathBuf::from(f),
-            };
-            tracing::info!("loading the config");
-            let config_str = std::fs::read_to_string(&config)?;
-            let config: gen::Config = toml::from_str(&config_str)?;
 
-            let lm_model_file = match lm_model_file {
-                None => repo.get(&config.moshi_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
-            let mimi_model_file = match mimi_model_file {
-                None => repo.get(&config.mimi_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
-            let text_tokenizer_file = match text_tokenizer {
-                None => repo.get(&config.tokenizer_name)?,
-                Some(v) => std::path::PathBuf::from(v),
-            };
+            let raw_options =
+                [input_format.is_some(), input_rate.is_some(), input_channels.is_some()];
+            if raw_options.contains(&true) && input_file.as_deref() != Some("-") {
+                anyhow::bail!(
+                    "--input-format, --input-rate and --input-channels only apply to raw PCM \
+                     on stdin, with --input-file -"
+                );
+            }
+            let default_raw = stream::RawInputFormat::default();
 
             let stream_config = stream::StreamConfig {
-                input_file: input_file.map(std::path::PathBuf::from),
+                input_file: input_file.map(PathBuf::from),
                 input_device,
+                audio_host,
+                strict_devices: strict,
+                raw_input: stream::RawInputFormat {
+                    sample_format: input_format.unwrap_or(default_raw.sample_format),
+                    sample_rate: input_rate.unwrap_or(default_raw.sample_rate),
+                    channels: input_channels.unwrap_or(default_raw.channels),
+                },
+                input_channel,
+                no_pace,
                 output_device,
                 disable_speaker,
-                save_output: save_output.map(std::path::PathBuf::from),
-                lm_config: config.model,
-                lm_model_file,
-                mimi_model_file,
-                text_tokenizer: text_tokenizer_file,
-                seed,
-                cfg_alpha,
+                save_output: save_output.map(PathBuf::from),
+                output: output.config(),
+                save_input: save_input.map(PathBuf::from),
+                save_stereo: save_stereo.map(PathBuf::from),
+                stdout_pcm,
+                subtitles: subtitles.map(PathBuf::from),
+                events: events.map(PathBuf::from),
+                vad: vad.config(),
             };
-            
-            stream::run(stream_config, &dev)?
+
+            if dry_run {
+                tracing::info!("Dry run: echoing the input instead of running the model");
+                return stream::run(
+                    stream_config,
+                    stream::EchoProcessor::new(DRY_RUN_DELAY_FRAMES),
+                );
+            }
+
+            let dev = device(cpu)?;
+            let files = ModelFiles::resolve(
+                &hf_repo,
+                config.map(PathBuf::from),
+                lm_model_file.map(PathBuf::from),
+                mimi_model_file.map(PathBuf::from),
+                text_tokenizer.map(PathBuf::from),
+            )?;
+
+            tracing::info!("Loading models...");
+            let sampling = sampling.resolve(&files.config.sampling);
+            let translator = Translator::with_sampling(&files, &sampling, cfg_alpha, &dev)?;
+            stream::run(stream_config, translator)?
+        }
+        Command::Serve {
+            addr,
+            max_connections,
+            dry_run,
+            lm_model_file,
+            mimi_model_file,
+            config,
+            text_tokenizer,
+            hf_repo,
+            sampling,
+            cfg_alpha,
+            cpu,
+        } => {
+            tracing_subscriber::fmt::init();
+            let args = serve::Args { addr, max_connections };
+            if dry_run {
+                tracing::info!("Dry run: echoing the input instead of running the model");
+                return serve::run(&args, || Ok(stream::EchoProcessor::new(DRY_RUN_DELAY_FRAMES)));
+            }
+
+            let dev = device(cpu)?;
+            let files = ModelFiles::resolve(
+                &hf_repo,
+                config.map(PathBuf::from),
+                lm_model_file.map(PathBuf::from),
+                mimi_model_file.map(PathBuf::from),
+                text_tokenizer.map(PathBuf::from),
+            )?;
+
+            tracing::info!("Loading models...");
+            let sampling = sampling.resolve(&files.config.sampling);
+            let translator = Translator::with_sampling(&files, &sampling, cfg_alpha, &dev)?;
+            // Every connection gets its own state on top of the shared weights.
+            serve::run(&args, move || Ok(translator.fork()))?
+        }
+        Command::Http {
+            addr,
+            max_sync_seconds,
+            dry_run,
+            lm_model_file,
+            mimi_model_file,
+            config,
+            text_tokenizer,
+            hf_repo,
+            sampling,
+            cfg_alpha,
+            cpu,
+        } => {
+            tracing_subscriber::fmt::init();
+            let args = http::Args { addr, max_sync_seconds };
+            if dry_run {
+                tracing::info!("Dry run: echoing the input instead of running the model");
+                return http::run(&args, stream::EchoProcessor::new(DRY_RUN_DELAY_FRAMES));
+            }
+
+            let dev = device(cpu)?;
+            let files = ModelFiles::resolve(
+                &hf_repo,
+                config.map(PathBuf::from),
+                lm_model_file.map(PathBuf::from),
+                mimi_model_file.map(PathBuf::from),
+                text_tokenizer.map(PathBuf::from),
+            )?;
+
+            tracing::info!("Loading models...");
+            let sampling = sampling.resolve(&files.config.sampling);
+            let translator = Translator::with_sampling(&files, &sampling, cfg_alpha, &dev)?;
+            http::run(&args, translator)?
         }
     }
     Ok(())
@@ -0,0 +1,146 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{bail, Result};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+
+pub const DEFAULT_SEED: u64 = 299_792_458;
+
+fn default_seed() -> u64 {
+    DEFAULT_SEED
+}
+
+/// How the tokens of one stream, text or audio, are sampled from the logits.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SamplingParams {
+    /// Softmax temperature, 0 picks the most likely token.
+    pub temperature: f64,
+    /// Only sample among the k most likely tokens.
+    pub top_k: Option<usize>,
+    /// Only sample among the most likely tokens reaching this cumulative
+    /// probability, applied after `top_k` when both are set.
+    pub top_p: Option<f64>,
+    /// Always pick the most likely token, regardless of the other settings.
+    #[serde(default)]
+    pub greedy: bool,
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+}
+
+impl SamplingParams {
+    pub fn top_k(k: usize, temperature: f64) -> Self {
+        Self { temperature, top_k: Some(k), top_p: None, greedy: false, seed: DEFAULT_SEED }
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        let temperature = self.temperature;
+        if self.greedy || temperature <= 0. {
+            return Sampling::ArgMax;
+        }
+        match (self.top_k, self.top_p) {
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (None, None) => Sampling::All { temperature },
+        }
+    }
+
+    pub fn logits_processor(&self) -> LogitsProcessor {
+        LogitsProcessor::from_sampling(self.seed, self.sampling())
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        if !self.temperature.is_finite() || self.temperature < 0. {
+            bail!("{name} temperature must be non-negative, got {}", self.temperature)
+        }
+        if self.top_k == Some(0) {
+            bail!("{name} top-k must be at least 1")
+        }
+        if let Some(p) = self.top_p {
+            if !(p > 0. && p <= 1.) {
+                bail!("{name} top-p must be in (0, 1], got {p}")
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sampling of the text and audio tokens, the `[sampling.text]` and
+/// `[sampling.audio]` sections of the model config.
+///
+/// The fields missing from a section keep the defaults of its stream.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(from = "SamplingSections")]
+pub struct SamplingConfig {
+    pub text: SamplingParams,
+    pub audio: SamplingParams,
+}
+
+/// Changes to the sampling of a stream, from a section of the config as
+/// written or from the command line. A `top_k` of 0 disables top-k and a
+/// `top_p` of 1 disables top-p.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct SamplingOverrides {
+    pub temperature: Option<f64>,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f64>,
+    pub greedy: Option<bool>,
+    pub seed: Option<u64>,
+}
+
+impl SamplingOverrides {
+    pub fn apply(&self, params: &mut SamplingParams) {
+        if let Some(temperature) = self.temperature {
+            params.temperature = temperature
+        }
+        if let Some(top_k) = self.top_k {
+            params.top_k = (top_k > 0).then_some(top_k)
+        }
+        if let Some(top_p) = self.top_p {
+            params.top_p = (top_p != 1.).then_some(top_p)
+        }
+        if let Some(greedy) = self.greedy {
+            params.greedy = greedy
+        }
+        if let Some(seed) = self.seed {
+            params.seed = seed
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct SamplingSections {
+    text: SamplingOverrides,
+    audio: SamplingOverrides,
+}
+
+impl From<SamplingSections> for SamplingConfig {
+    fn from(sections: SamplingSections) -> Self {
+        let mut config = Self::default();
+        sections.text.apply(&mut config.text);
+        sections.audio.apply(&mut config.audio);
+        config
+    }
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self { text: SamplingParams::top_k(25, 0.8), audio: SamplingParams::top_k(250, 0.8) }
+    }
+}
+
+impl SamplingConfig {
+    /// Uses the same seed for the text and audio streams.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.text.seed = seed;
+        self.audio.seed = seed;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.text.validate("text")?;
+        self.audio.validate("audio")
+    }
+}
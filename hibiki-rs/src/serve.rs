@@ -0,0 +1,525 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! WebSocket server translating audio streamed by network clients.
+//!
+//! Clients connect to `ws://<addr>/?format=<f32le|s16le|opus>&sample_rate=<hz>&channels=<n>`
+//! and send their audio as binary messages. The translated audio comes back as
+//! binary messages in the same format (24kHz mono) and the text and timing as
+//! JSON text messages, using the same [`Event`]s as `--events`. Clients send
+//! `{"command": "end"}` once their input is over to get the end of the
+//! translation, or `{"command": "reset"}` to end the session and start over.
+
+use anyhow::{bail, Result};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::{Message, WebSocket};
+
+use crate::events::{Event, FrameOutput};
+use crate::stream::resampler::{InputChannel, StreamingResampler, FRAME_SIZE, TARGET_SAMPLE_RATE};
+use crate::stream::FrameProcessor;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8998";
+pub const DEFAULT_MAX_CONNECTIONS: usize = 4;
+/// How often blocked reads and accepts check for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Clients have this long to complete the websocket opening and closing
+/// handshakes.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Silence fed after the end of the input so that the model gets to translate
+/// the end of the speech, as for files.
+const TAIL_SAMPLES: usize = 12_000;
+/// Opus packets sent to clients hold 20ms of audio.
+const OPUS_FRAME_SIZE: usize = 480;
+/// Longest opus packet clients can send, 120ms at 24kHz.
+const OPUS_MAX_FRAME_SIZE: usize = 2880;
+const OPUS_MAX_PACKET_BYTES: usize = 1276;
+const OPUS_BITRATE: i32 = 32_000;
+
+/// Encoding of the audio exchanged with a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    /// Interleaved little-endian 32-bit floats.
+    F32Le,
+    /// Interleaved little-endian 16-bit integers.
+    S16Le,
+    /// One opus packet per message, decoded at 24kHz.
+    Opus,
+}
+
+impl std::str::FromStr for AudioFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "f32le" => Ok(Self::F32Le),
+            "s16le" => Ok(Self::S16Le),
+            "opus" => Ok(Self::Opus),
+            _ => bail!("unknown audio format {s}, expected f32le, s16le or opus"),
+        }
+    }
+}
+
+/// Audio settings of a connection, given as query parameters of the url.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionParams {
+    pub format: AudioFormat,
+    /// Sample rate of the client audio, ignored for opus.
+    pub sample_rate: usize,
+    pub channels: usize,
+}
+
+impl Default for SessionParams {
+    fn default() -> Self {
+        Self { format: AudioFormat::F32Le, sample_rate: TARGET_SAMPLE_RATE, channels: 1 }
+    }
+}
+
+impl SessionParams {
+    pub fn from_query(query: Option<&str>) -> Result<Self> {
+        let mut params = Self::default();
+        for pair in query.unwrap_or_default().split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "format" => params.format = value.parse()?,
+                "sample_rate" => params.sample_rate = value.parse()?,
+                "channels" => params.channels = value.parse()?,
+                _ => bail!("unknown parameter {key}"),
+            }
+        }
+        if params.sample_rate == 0 {
+            bail!("sample_rate must be positive")
+        }
+        let max_channels = if params.format == AudioFormat::Opus { 2 } else { 8 };
+        if params.channels == 0 || params.channels > max_channels {
+            bail!("channels must be between 1 and {max_channels}")
+        }
+        Ok(params)
+    }
+}
+
+/// Messages clients send as text.
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// The input is over, translate what is left and close the connection.
+    End,
+    /// Start an independent session on the same connection.
+    Reset,
+}
+
+pub struct Args {
+    pub addr: String,
+    pub max_connections: usize,
+}
+
+/// Serves until Ctrl-C is pressed, creating a processor per connection with
+/// `new_session`.
+pub fn run<P, F>(args: &Args, new_session: F) -> Result<()>
+where
+    P: FrameProcessor + 'static,
+    F: Fn() -> Result<P> + Send + Sync + 'static,
+{
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_ctrlc = shutdown.clone();
+    ctrlc::set_handler(move || {
+        tracing::info!("Shutdown signal received");
+        shutdown_ctrlc.store(true, Ordering::Relaxed);
+    })?;
+
+    let listener = TcpListener::bind(&args.addr)?;
+    tracing::info!(addr = %listener.local_addr()?, "listening");
+    serve(listener, args.max_connections, new_session, shutdown)
+}
+
+/// Accepts connections on `listener` until `shutdown` is set, each one being
+/// handled on its own thread with a processor created by `new_session`.
+///
+/// Connections beyond `max_connections` are rejected with a 503 during the
+/// handshake.
+pub fn serve<P, F>(
+    listener: TcpListener,
+    max_connections: usize,
+    new_session: F,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()>
+where
+    P: FrameProcessor + 'static,
+    F: Fn() -> Result<P> + Send + Sync + 'static,
+{
+    listener.set_nonblocking(true)?;
+    let new_session = Arc::new(new_session);
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut handles: Vec<std::thread::JoinHandle<()>> = vec![];
+    while !shutdown.load(Ordering::Relaxed) {
+        let (stream, peer) = match listener.accept() {
+            Ok(conn) => conn,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("accept failed: {e}");
+                continue;
+            }
+        };
+        handles.retain(|h| !h.is_finished());
+        let slot = Slot::acquire(&active, max_connections);
+        let new_session = new_session.clone();
+        let shutdown = shutdown.clone();
+        let handle = std::thread::Builder::new().name(format!("conn-{peer}")).spawn(move || {
+            match handle_connection(stream, slot, new_session.as_ref(), &shutdown) {
+                Ok(()) => tracing::info!(%peer, "connection closed"),
+                Err(e) => tracing::warn!(%peer, "connection failed: {e}"),
+            }
+        })?;
+        handles.push(handle);
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+/// One of the `max_connections` sessions, released when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn acquire(active: &Arc<AtomicUsize>, max_connections: usize) -> Option<Self> {
+        if active.fetch_add(1, Ordering::SeqCst) >= max_connections {
+            active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Self(active.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message));
+    *response.status_mut() = status;
+    response
+}
+
+fn handle_connection<P, F>(
+    stream: TcpStream,
+    slot: Option<Slot>,
+    new_session: &F,
+    shutdown: &AtomicBool,
+) -> Result<()>
+where
+    P: FrameProcessor,
+    F: Fn() -> Result<P>,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut params = None;
+    // The error type is imposed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        if slot.is_none() {
+            return Err(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "too many connections".to_string(),
+            ));
+        }
+        match SessionParams::from_query(request.uri().query()) {
+            Ok(p) => {
+                params = Some(p);
+                Ok(response)
+            }
+            Err(e) => Err(error_response(StatusCode::BAD_REQUEST, e.to_string())),
+        }
+    };
+    let mut ws = match tungstenite::accept_hdr(stream, callback) {
+        Ok(ws) => ws,
+        Err(e) => bail!("handshake failed: {e}"),
+    };
+    let params = match params {
+        Some(params) => params,
+        None => bail!("handshake completed without parameters"),
+    };
+    ws.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    tracing::info!(?params, "connection opened");
+
+    let processor = match new_session() {
+        Ok(processor) => processor,
+        Err(e) => {
+            send_event(&mut ws, &Event::Error { message: e.to_string() })?;
+            ws.close(None)?;
+            ws.flush()?;
+            return Err(e);
+        }
+    };
+    let mut session = Session::new(params, processor)?;
+    session.start(&mut ws)?;
+    let result = session.run(&mut ws, shutdown);
+    if let Err(e) = result.as_ref() {
+        let _ = send_event(&mut ws, &Event::Error { message: e.to_string() });
+        let _ = ws.close(Some(CloseFrame { code: CloseCode::Error, reason: "".into() }));
+        let _ = ws.flush();
+    }
+    result
+}
+
+fn send_event(ws: &mut WebSocket<TcpStream>, event: &Event) -> Result<()> {
+    ws.write(Message::text(serde_json::to_string(event)?))?;
+    Ok(())
+}
+
+/// Translation state of a connection.
+struct Session<P> {
+    processor: P,
+    params: SessionParams,
+    resampler: StreamingResampler,
+    opus_decoder: Option<opus_rs::OpusDecoder>,
+    opus_encoder: Option<opus_rs::OpusEncoder>,
+    /// Generated audio not sent yet, opus packets having a fixed size.
+    opus_pending: Vec<f32>,
+    step: usize,
+    samples_generated: usize,
+}
+
+impl<P: FrameProcessor> Session<P> {
+    fn new(params: SessionParams, processor: P) -> Result<Self> {
+        let (resampler, opus_decoder, opus_encoder) = Self::audio_state(&params)?;
+        Ok(Self {
+            processor,
+            params,
+            resampler,
+            opus_decoder,
+            opus_encoder,
+            opus_pending: vec![],
+            step: 0,
+            samples_generated: 0,
+        })
+    }
+
+    /// Creates the resampler and the opus codecs of a new session.
+    fn audio_state(
+        params: &SessionParams,
+    ) -> Result<(StreamingResampler, Option<opus_rs::OpusDecoder>, Option<opus_rs::OpusEncoder>)>
+    {
+        let (sample_rate, opus_decoder, opus_encoder) = if params.format == AudioFormat::Opus {
+            let decoder = opus_rs::OpusDecoder::new(TARGET_SAMPLE_RATE as i32, params.channels)
+                .map_err(anyhow::Error::msg)?;
+            let mut encoder = opus_rs::OpusEncoder::new(
+                TARGET_SAMPLE_RATE as i32,
+                1,
+                opus_rs::Application::Audio,
+            )
+            .map_err(anyhow::Error::msg)?;
+            encoder.bitrate_bps = OPUS_BITRATE;
+            (TARGET_SAMPLE_RATE, Some(decoder), Some(encoder))
+        } else {
+            (params.sample_rate, None, None)
+        };
+        let resampler = StreamingResampler::new(sample_rate, params.channels, InputChannel::Mix)?;
+        Ok((resampler, opus_decoder, opus_encoder))
+    }
+
+    fn start(&mut self, ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        send_event(ws, &Event::session_start("serve", self.processor.info()))?;
+        ws.flush()?;
+        Ok(())
+    }
+
+    /// Handles messages until the client closes the connection or the server
+    /// shuts down. Once the session ended, the client gets `HANDSHAKE_TIMEOUT`
+    /// to complete the close handshake before the connection is dropped.
+    fn run(&mut self, ws: &mut WebSocket<TcpStream>, shutdown: &AtomicBool) -> Result<()> {
+        // When the close handshake started, once the session ended.
+        let mut closing: Option<Instant> = None;
+        loop {
+            let ended = closing.is_some();
+            if shutdown.load(Ordering::Relaxed) && !ended {
+                closing = Some(Instant::now());
+                ws.close(Some(CloseFrame { code: CloseCode::Away, reason: "".into() }))?;
+            }
+            if closing.is_some_and(|start| start.elapsed() >= HANDSHAKE_TIMEOUT) {
+                tracing::warn!("the client did not close the connection, dropping it");
+                return Ok(());
+            }
+            let message = match ws.read() {
+                Ok(message) => message,
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if closing.is_some() {
+                        // Keep the close handshake going.
+                        ws.flush()?;
+                    }
+                    continue;
+                }
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            match message {
+                // Invalid input is reported to the client, model errors end
+                // the connection.
+                Message::Binary(data) if !ended => match self.decode(&data) {
+                    Ok(pcm) => self.push_audio(&pcm, ws)?,
+                    Err(e) => send_event(ws, &Event::Error { message: e.to_string() })?,
+                },
+                Message::Text(text) if !ended => match serde_json::from_str(text.as_str()) {
+                    Ok(Command::End) => {
+                        self.finish(ws)?;
+                        closing = Some(Instant::now());
+                        ws.close(None)?;
+                    }
+                    Ok(Command::Reset) => {
+                        self.reset(ws)?;
+                        self.start(ws)?;
+                    }
+                    Err(e) => {
+                        let message = format!("invalid command: {e}");
+                        send_event(ws, &Event::Error { message })?;
+                    }
+                },
+                _ => {}
+            }
+            ws.flush()?;
+        }
+    }
+
+    /// Decodes a message of client audio into interleaved samples.
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>> {
+        let channels = self.params.channels;
+        let pcm = match self.params.format {
+            AudioFormat::F32Le => {
+                check_length(data.len(), 4 * channels)?;
+                data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+            }
+            AudioFormat::S16Le => {
+                check_length(data.len(), 2 * channels)?;
+                data.chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.)
+                    .collect()
+            }
+            AudioFormat::Opus => {
+                let mut pcm = vec![0f32; OPUS_MAX_FRAME_SIZE * channels];
+                let decoder = self.opus_decoder.as_mut().expect("opus sessions have a decoder");
+                let samples = decoder
+                    .decode(data, OPUS_MAX_FRAME_SIZE, &mut pcm)
+                    .map_err(|e| anyhow::anyhow!("invalid opus packet: {e}"))?;
+                pcm.truncate(samples * channels);
+                pcm
+            }
+        };
+        Ok(pcm)
+    }
+
+    /// Translates the complete frames of client audio.
+    fn push_audio(&mut self, pcm: &[f32], ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        for frame in self.resampler.push_samples(pcm)? {
+            self.process(&frame, ws)?;
+        }
+        Ok(())
+    }
+
+    /// Translates one frame and sends what was generated.
+    fn process(&mut self, frame: &[f32; FRAME_SIZE], ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        let start = Instant::now();
+        let (pcm, text) = self.processor.process_frame(frame)?;
+        let output = FrameOutput {
+            step: self.step,
+            input: frame,
+            offset: self.samples_generated,
+            pcm: &pcm,
+            text: text.as_deref(),
+            text_tokens: self.processor.last_text_tokens(),
+            processing_time: start.elapsed(),
+        };
+        for event in output.events() {
+            send_event(ws, &event)?;
+        }
+        self.step += 1;
+        self.samples_generated += pcm.len();
+        self.send_audio(&pcm, ws)
+    }
+
+    fn send_audio(&mut self, pcm: &[f32], ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        if pcm.is_empty() {
+            return Ok(());
+        }
+        match self.params.format {
+            AudioFormat::F32Le => {
+                let bytes = pcm.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>();
+                ws.write(Message::binary(bytes))?;
+            }
+            AudioFormat::S16Le => {
+                let bytes = pcm
+                    .iter()
+                    .flat_map(|s| ((s.clamp(-1., 1.) * 32767.).round() as i16).to_le_bytes())
+                    .collect::<Vec<u8>>();
+                ws.write(Message::binary(bytes))?;
+            }
+            AudioFormat::Opus => {
+                self.opus_pending.extend_from_slice(pcm);
+                let encoder = self.opus_encoder.as_mut().expect("opus sessions have an encoder");
+                let mut packet = [0u8; OPUS_MAX_PACKET_BYTES];
+                let mut pos = 0;
+                while self.opus_pending.len() - pos >= OPUS_FRAME_SIZE {
+                    let frame = &self.opus_pending[pos..pos + OPUS_FRAME_SIZE];
+                    let len = encoder
+                        .encode(frame, OPUS_FRAME_SIZE, &mut packet)
+                        .map_err(anyhow::Error::msg)?;
+                    ws.write(Message::binary(packet[..len].to_vec()))?;
+                    pos += OPUS_FRAME_SIZE;
+                }
+                self.opus_pending.drain(..pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Translates the end of the input followed by some silence and reports the
+    /// session stats.
+    fn finish(&mut self, ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        if let Some(frame) = self.resampler.flush()? {
+            self.process(&frame, ws)?;
+        }
+        for _ in 0..TAIL_SAMPLES.div_ceil(FRAME_SIZE) {
+            self.process(&[0.; FRAME_SIZE], ws)?;
+        }
+        if self.params.format == AudioFormat::Opus && !self.opus_pending.is_empty() {
+            let padding = OPUS_FRAME_SIZE - self.opus_pending.len();
+            self.send_audio(&vec![0.; padding], ws)?;
+        }
+        send_event(ws, &Event::session_end(&self.processor.stats(), self.samples_generated, None))
+    }
+
+    /// Ends the current session, dropping the input and the generated audio
+    /// that are still buffered, and starts from a fresh state.
+    fn reset(&mut self, ws: &mut WebSocket<TcpStream>) -> Result<()> {
+        send_event(ws, &Event::session_end(&self.processor.stats(), self.samples_generated, None))?;
+        (self.resampler, self.opus_decoder, self.opus_encoder) = Self::audio_state(&self.params)?;
+        self.processor.reset();
+        self.opus_pending.clear();
+        self.step = 0;
+        self.samples_generated = 0;
+        Ok(())
+    }
+}
+
+fn check_length(len: usize, bytes_per_frame: usize) -> Result<()> {
+    if !len.is_multiple_of(bytes_per_frame) {
+        bail!("audio messages must hold whole samples for every channel, got {len} bytes")
+    }
+    Ok(())
+}
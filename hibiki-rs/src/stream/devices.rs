@@ -5,130 +5,377 @@
 use anyhow::{bail, Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait};
 
-/// List all available input and output devices
-pub fn list_devices() -> Result<()> {
-    let host = cpal::default_host();
-    
-    println!("\n=== Input Devices ===");
-    let input_devices: Vec<_> = host.input_devices()?.collect();
-    if input_devices.is_empty() {
-        println!("  (none)");
-    } else {
-        for (idx, device) in input_devices.iter().enumerate() {
-            let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
-            println!("  {}. {}", idx + 1, name);
+use super::resampler::TARGET_SAMPLE_RATE;
+
+/// A stream config a device supports, over a range of sample rates.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConfigRangeInfo {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+/// The config a device uses by default.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConfigInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeviceInfo {
+    /// Position in the listing, starting at 1.
+    pub index: usize,
+    pub name: String,
+    /// Whether this is the default device of the host for its direction.
+    pub is_default: bool,
+    pub default_config: Option<ConfigInfo>,
+    pub supported_configs: Vec<ConfigRangeInfo>,
+}
+
+/// The audio devices of the host, as printed by `--list-devices`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeviceList {
+    pub host: String,
+    /// Hosts that `--audio-host` accepts in this build.
+    pub available_hosts: Vec<String>,
+    pub input: Vec<DeviceInfo>,
+    pub output: Vec<DeviceInfo>,
+}
+
+/// How `--input-device` and `--output-device` designate a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceQuery {
+    /// Position in `--list-devices`, starting at 1.
+    Index(usize),
+    /// Exact name, or else a case-insensitive substring of the name.
+    Name(String),
+    /// The `occurrence`-th device, starting at 1, among the ones named exactly
+    /// `name`.
+    Pinned { name: String, occurrence: usize },
+}
+
+impl DeviceQuery {
+    /// The query to look the device at `idx` in `names` up again once it was
+    /// lost. Indices shift when devices come and go and several devices can
+    /// share a name, so the device is then found by its exact name and its
+    /// rank among the devices with that name.
+    pub fn pinned(names: &[String], idx: usize) -> Self {
+        let name = names[idx].clone();
+        let occurrence = names[..=idx].iter().filter(|n| **n == name).count();
+        Self::Pinned { name, occurrence }
+    }
+}
+
+impl std::str::FromStr for DeviceQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().parse::<usize>() {
+            Ok(0) => bail!("device indices start at 1"),
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) => Ok(Self::Name(s.to_string())),
         }
     }
+}
+
+impl std::fmt::Display for DeviceQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{index}"),
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Pinned { name, occurrence: 1 } => write!(f, "{name}"),
+            Self::Pinned { name, occurrence } => write!(f, "{name} (#{occurrence} of that name)"),
+        }
+    }
+}
+
+/// Where and how the audio devices are looked up.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceSelector {
+    pub host: cpal::HostId,
+    /// Fail when a query is ambiguous or, for the output, matches nothing,
+    /// instead of using the first match or the default device.
+    pub strict: bool,
+}
+
+impl DeviceSelector {
+    /// Uses the host named `host`, e.g. ALSA or JACK on Linux, or the default
+    /// host when None.
+    pub fn new(host: Option<&str>, strict: bool) -> Result<Self> {
+        Ok(Self { host: find_host(host)?, strict })
+    }
+
+    pub fn host(&self) -> Result<cpal::Host> {
+        Ok(cpal::host_from_id(self.host)?)
+    }
+}
+
+/// Looks up an available cpal host by case-insensitive name, the default host
+/// when None. JACK is only available in builds with the `jack` feature.
+pub fn find_host(name: Option<&str>) -> Result<cpal::HostId> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host().id());
+    };
+    let available = cpal::available_hosts();
+    available.iter().copied().find(|id| id.name().eq_ignore_ascii_case(name)).with_context(|| {
+        let names: Vec<_> = available.iter().map(|id| id.name()).collect();
+        format!("Audio host '{}' is not available, available hosts: {}", name, names.join(", "))
+    })
+}
+
+/// Describes the input and output devices of a host
+pub fn device_list(host: cpal::HostId) -> Result<DeviceList> {
+    let host = cpal::host_from_id(host)?;
+    let name_of = |device: Option<cpal::Device>| device.and_then(|d| d.name().ok());
+    // Only the first device with the default name is marked, same-named devices
+    // cannot be told apart.
+    let mut default_input = name_of(host.default_input_device());
+    let mut default_output = name_of(host.default_output_device());
+    
+    let mut input = vec![];
+    for (idx, device) in host.input_devices()?.enumerate() {
+        let supported = device.supported_input_configs().map(|c| c.collect()).unwrap_or_default();
+        let default_config = device.default_input_config().ok();
+        input.push(device_info(idx, &device, &mut default_input, default_config, supported));
+    }
+    let mut output = vec![];
+    for (idx, device) in host.output_devices()?.enumerate() {
+        let supported = device.supported_output_configs().map(|c| c.collect()).unwrap_or_default();
+        let default_config = device.default_output_config().ok();
+        output.push(device_info(idx, &device, &mut default_output, default_config, supported));
+    }
+    
+    let available_hosts = cpal::available_hosts().iter().map(|id| id.name().to_string()).collect();
+    Ok(DeviceList { host: host.id().name().to_string(), available_hosts, input, output })
+}
+
+fn device_info(
+    idx: usize,
+    device: &cpal::Device,
+    default_name: &mut Option<String>,
+    default_config: Option<cpal::SupportedStreamConfig>,
+    supported: Vec<cpal::SupportedStreamConfigRange>,
+) -> DeviceInfo {
+    let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
+    DeviceInfo {
+        index: idx + 1,
+        is_default: default_name.take_if(|default| *default == name).is_some(),
+        name,
+        default_config: default_config.map(|c| ConfigInfo {
+            channels: c.channels(),
+            sample_rate: c.sample_rate().0,
+            sample_format: c.sample_format().to_string(),
+        }),
+        supported_configs: supported
+            .iter()
+            .map(|c| ConfigRangeInfo {
+                channels: c.channels(),
+                min_sample_rate: c.min_sample_rate().0,
+                max_sample_rate: c.max_sample_rate().0,
+                sample_format: c.sample_format().to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// List all available input and output devices, with their configs, as text
+/// or as JSON
+pub fn list_devices(host: cpal::HostId, json: bool) -> Result<()> {
+    let devices = device_list(host)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
     
-    println!("\n=== Output Devices ===");
-    let output_devices: Vec<_> = host.output_devices()?.collect();
-    if output_devices.is_empty() {
-        println!("  (none)");
-    } else {
-        for (idx, device) in output_devices.iter().enumerate() {
-            let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
-            println!("  {}. {}", idx + 1, name);
+    println!("Host: {} (available: {})", devices.host, devices.available_hosts.join(", "));
+    for (title, list) in [("Input", &devices.input), ("Output", &devices.output)] {
+        println!("\n=== {} Devices ===", title);
+        if list.is_empty() {
+            println!("  (none)");
+        }
+        for device in list {
+            let default = if device.is_default { " (default)" } else { "" };
+            println!("  {}. {}{}", device.index, device.name, default);
+            if let Some(ref config) = device.default_config {
+                println!(
+                    "     default: {} ch, {} Hz, {}",
+                    config.channels, config.sample_rate, config.sample_format
+                );
+            }
+            for config in &device.supported_configs {
+                let rates = if config.min_sample_rate == config.max_sample_rate {
+                    format!("{} Hz", config.min_sample_rate)
+                } else {
+                    format!("{}-{} Hz", config.min_sample_rate, config.max_sample_rate)
+                };
+                let (channels, format) = (config.channels, &config.sample_format);
+                println!("     supports: {} ch, {}, {}", channels, rates, format);
+            }
         }
     }
     
     Ok(())
 }
 
-/// Find input device by case-insensitive substring match
-pub fn find_input_device(query: &str) -> Result<cpal::Device> {
-    let host = cpal::default_host();
-    let query_lower = query.to_lowercase();
+/// Picks the config of the speaker stream among the ones the device supports.
+///
+/// Mono 24kHz avoids any conversion. Otherwise 24kHz with the fewest channels
+/// is used, then the device default, then the rate closest to 24kHz. Among
+/// otherwise equal configs f32 is preferred, then the widest samples. Returns
+/// None when the device has no config with a sample format that can be built.
+pub fn choose_output_config(
+    supported: &[cpal::SupportedStreamConfigRange],
+    default: Option<cpal::SupportedStreamConfig>,
+) -> Option<cpal::SupportedStreamConfig> {
+    let target = cpal::SampleRate(TARGET_SAMPLE_RATE as u32);
+    let mut configs: Vec<_> =
+        supported.iter().filter(|c| format_rank(c.sample_format()).is_some()).copied().collect();
+    configs.sort_by_key(|c| (c.channels(), format_rank(c.sample_format())));
     
-    let mut matches = vec![];
-    for device in host.input_devices()? {
-        if let Ok(name) = device.name() {
-            if name.to_lowercase().contains(&query_lower) {
-                matches.push((device, name));
+    if let Some(config) = configs.iter().find_map(|c| c.try_with_sample_rate(target)) {
+        return Some(config);
+    }
+    if let Some(default) = default.filter(|c| format_rank(c.sample_format()).is_some()) {
+        return Some(default);
+    }
+    configs
+        .into_iter()
+        .min_by_key(|c| {
+            let rate = target.clamp(c.min_sample_rate(), c.max_sample_rate());
+            (rate.0.abs_diff(target.0), c.channels(), format_rank(c.sample_format()))
+        })
+        .map(|c| {
+            let rate = target.clamp(c.min_sample_rate(), c.max_sample_rate());
+            c.with_sample_rate(rate)
+        })
+}
+
+/// Preference of the speaker stream for a sample format, lower is better:
+/// f32 needs no conversion, then wider samples lose less. None for the formats
+/// the stream cannot be built with.
+fn format_rank(format: cpal::SampleFormat) -> Option<(bool, std::cmp::Reverse<usize>)> {
+    use cpal::SampleFormat::*;
+    match format {
+        I8 | I16 | I32 | I64 | U8 | U16 | U32 | U64 | F32 | F64 => {
+            Some((format != F32, std::cmp::Reverse(format.sample_size())))
+        }
+        _ => None,
+    }
+}
+
+/// Picks the position of the device designated by `query` among `names`,
+/// listed in the order of `--list-devices`. Returns None when none matches.
+///
+/// An exact name match wins over case-insensitive substring matches. When
+/// several devices match, the first one is used, or an error is returned in
+/// strict mode.
+pub fn select_device(names: &[String], query: &DeviceQuery, strict: bool) -> Result<Option<usize>> {
+    let matches: Vec<usize> = match query {
+        DeviceQuery::Index(index) => return Ok(index.checked_sub(1).filter(|i| *i < names.len())),
+        DeviceQuery::Pinned { name, occurrence } => {
+            let mut same_name = (0..names.len()).filter(|&i| names[i] == *name);
+            return Ok(occurrence.checked_sub(1).and_then(|n| same_name.nth(n)));
+        }
+        DeviceQuery::Name(query) => {
+            let exact: Vec<usize> = (0..names.len()).filter(|&i| names[i] == *query).collect();
+            if exact.is_empty() {
+                let query_lower = query.to_lowercase();
+                (0..names.len())
+                    .filter(|&i| names[i].to_lowercase().contains(&query_lower))
+                    .collect()
+            } else {
+                exact
             }
         }
+    };
+    
+    if matches.len() > 1 {
+        let listed: Vec<String> =
+            matches.iter().map(|&i| format!("{}. {}", i + 1, names[i])).collect();
+        if strict {
+            bail!(
+                "{} devices match '{}' ({}), pick one by its index from --list-devices",
+                matches.len(),
+                query,
+                listed.join(", ")
+            );
+        }
+        tracing::warn!("Multiple devices matched '{}', using first: {}", query, listed[0]);
+        for other in listed.iter().skip(1) {
+            tracing::warn!("  Also matched: {}", other);
+        }
     }
+    Ok(matches.first().copied())
+}
+
+fn device_names(devices: &[cpal::Device]) -> Vec<String> {
+    devices.iter().map(|d| d.name().unwrap_or_else(|_| "(unknown)".to_string())).collect()
+}
+
+/// Find the input device designated by `query`, along with the query pinning
+/// it for later lookups
+pub fn find_input_device(
+    selector: &DeviceSelector,
+    query: &DeviceQuery,
+) -> Result<(cpal::Device, DeviceQuery)> {
+    let host = selector.host()?;
+    let devices: Vec<_> = host.input_devices()?.collect();
+    let names = device_names(&devices);
     
-    match matches.len() {
-        0 => {
+    match select_device(&names, query, selector.strict)? {
+        Some(idx) => {
+            tracing::info!("Selected input device: {}. {}", idx + 1, names[idx]);
+            Ok((devices[idx].clone(), DeviceQuery::pinned(&names, idx)))
+        }
+        None => {
             eprintln!("No input device found matching '{}'", query);
             eprintln!("\nAvailable input devices:");
-            for device in host.input_devices()? {
-                if let Ok(name) = device.name() {
-                    eprintln!("  - {}", name);
-                }
+            for (idx, name) in names.iter().enumerate() {
+                eprintln!("  {}. {}", idx + 1, name);
             }
             bail!("No matching input device found");
         }
-        1 => {
-            let (device, name) = matches.into_iter().next().unwrap();
-            tracing::info!("Selected input device: {}", name);
-            Ok(device)
-        }
-        _ => {
-            let (device, name) = matches[0].clone();
-            tracing::warn!(
-                "Multiple devices matched '{}', using first: {}",
-                query,
-                name
-            );
-            for (_, other_name) in matches.iter().skip(1) {
-                tracing::warn!("  Also matched: {}", other_name);
-            }
-            Ok(device)
-        }
     }
 }
 
-/// Find output device by case-insensitive substring match, fallback to default
-pub fn find_output_device(query: Option<&str>) -> Result<cpal::Device> {
-    let host = cpal::default_host();
+/// Find the output device designated by `query`, fallback to the default
+/// device unless in strict mode. Also returns the query pinning the device for
+/// later lookups, None for the default device.
+pub fn find_output_device(
+    selector: &DeviceSelector,
+    query: Option<&DeviceQuery>,
+) -> Result<(cpal::Device, Option<DeviceQuery>)> {
+    let host = selector.host()?;
+    let default_device = || -> Result<cpal::Device> {
+        let device = host.default_output_device()
+            .context("No default output device available")?;
+        let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
+        tracing::info!("Using default output device: {}", name);
+        Ok(device)
+    };
     
     let query = match query {
-        None => {
-            let device = host.default_output_device()
-                .context("No default output device available")?;
-            let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
-            tracing::info!("Using default output device: {}", name);
-            return Ok(device);
-        }
+        None => return Ok((default_device()?, None)),
         Some(q) => q,
     };
     
-    let query_lower = query.to_lowercase();
-    let mut matches = vec![];
-    for device in host.output_devices()? {
-        if let Ok(name) = device.name() {
-            if name.to_lowercase().contains(&query_lower) {
-                matches.push((device, name));
-            }
-        }
-    }
-    
-    match matches.len() {
-        0 => {
-            tracing::warn!("No output device found matching '{}', using default", query);
-            let device = host.default_output_device()
-                .context("No default output device available")?;
-            let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
-            tracing::info!("Using default output device: {}", name);
-            Ok(device)
+    let devices: Vec<_> = host.output_devices()?.collect();
+    let names = device_names(&devices);
+    match select_device(&names, query, selector.strict)? {
+        Some(idx) => {
+            tracing::info!("Selected output device: {}. {}", idx + 1, names[idx]);
+            Ok((devices[idx].clone(), Some(DeviceQuery::pinned(&names, idx))))
         }
-        1 => {
-            let (device, name) = matches.into_iter().next().unwrap();
-            tracing::info!("Selected output device: {}", name);
-            Ok(device)
+        None if selector.strict => {
+            bail!("No output device found matching '{}', available: {}", query, names.join(", "))
         }
-        _ => {
-            let (device, name) = matches[0].clone();
-            tracing::warn!(
-                "Multiple devices matched '{}', using first: {}",
-                query,
-                name
-            );
-            for (_, other_name) in matches.iter().skip(1) {
-                tracing::warn!("  Also matched: {}", other_name);
-            }
-            Ok(device)
+        None => {
+            tracing::warn!("No output device found matching '{}', using default", query);
+            // The query is kept, to pick the device up if it shows up later.
+            Ok((default_device()?, Some(query.clone())))
         }
     }
 }
@@ -5,122 +5,208 @@
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, StreamTrait};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use super::resampler::{StreamingResampler, FRAME_SIZE, TARGET_SAMPLE_RATE};
+use super::devices::{self, DeviceQuery, DeviceSelector};
+use super::recovery::{self, StreamProbe, Watchdog};
+use super::resampler::{InputChannel, StreamingResampler, FRAME_SIZE, TARGET_SAMPLE_RATE};
+use crate::events::Event;
 
 pub type AudioFrame = [f32; FRAME_SIZE];
 
-/// Reads audio from a file, paces it to wall clock, and emits 80ms frames
-pub fn run_file_input<P: AsRef<Path>>(
-    path: P,
-    tx: mpsc::SyncSender<AudioFrame>,
-    shutdown: Arc<std::sync::atomic::AtomicBool>,
-) -> Result<()> {
-    use std::sync::atomic::Ordering;
-    
+/// Decodes an audio file and splits it into 80ms frames at 24kHz.
+///
+/// Half a second of silence is appended so that the model gets to translate
+/// the end of the speech, and the last partial frame is zero-padded.
+pub fn file_frames<P: AsRef<Path>>(path: P, channel: InputChannel) -> Result<Vec<AudioFrame>> {
     // Decode entire file
-    let (mut pcm, sample_rate) = crate::audio_io::pcm_decode(path)?;
+    let (pcm, sample_rate) = crate::audio_io::pcm_decode(path, channel)?;
     tracing::info!(
         "File decoded: {} samples at {} Hz",
         pcm.len(),
         sample_rate
     );
+    pcm_frames(pcm, sample_rate)
+}
+
+/// Splits decoded mono audio into 80ms frames at 24kHz, padded as in
+/// [`file_frames`].
+pub fn pcm_frames(mut pcm: Vec<f32>, sample_rate: u32) -> Result<Vec<AudioFrame>> {
+    if sample_rate == 0 {
+        anyhow::bail!("unknown sample rate")
+    }
     
     // Pad with silence at end
     pcm.extend_from_slice(&vec![0.0; 12000]);
     
-    // Create resampler if needed
-    let mut resampler = if sample_rate as usize != TARGET_SAMPLE_RATE {
+    let mut frames = Vec::with_capacity(pcm.len() / FRAME_SIZE + 1);
+    if sample_rate as usize != TARGET_SAMPLE_RATE {
         tracing::info!("Resampling from {} Hz to {} Hz", sample_rate, TARGET_SAMPLE_RATE);
-        Some(StreamingResampler::new(sample_rate as usize, 1)?)
+        let mut resampler = StreamingResampler::new(sample_rate as usize, 1, InputChannel::Mix)?;
+        frames.extend(resampler.push_samples(&pcm)?);
+        // Flush remaining
+        frames.extend(resampler.flush()?);
     } else {
-        None
-    };
+        // No resampling needed, split directly
+        for chunk in pcm.chunks(FRAME_SIZE) {
+            // Pad last frame
+            let mut frame = [0.0f32; FRAME_SIZE];
+            frame[..chunk.len()].copy_from_slice(chunk);
+            frames.push(frame);
+        }
+    }
+    Ok(frames)
+}
+
+/// Reads audio from a file, paces it to wall clock, and emits 80ms frames
+///
+/// When `pace` is false, frames are sent as fast as the receiver accepts them,
+/// relying on the bounded channel for backpressure.
+pub fn run_file_input<P: AsRef<Path>>(
+    path: P,
+    channel: InputChannel,
+    pace: bool,
+    tx: mpsc::SyncSender<AudioFrame>,
+    shutdown: Arc<std::sync::atomic::AtomicBool>,
+) -> Result<()> {
+    let frames = file_frames(path, channel)?;
     
     let frame_duration = Duration::from_millis(80);
     let start_time = Instant::now();
     let mut frame_idx = 0;
     
-    if let Some(ref mut resampler) = resampler {
-        // Need to resample
-        let frames = resampler.push_samples(&pcm)?;
-        for frame in frames {
-            if shutdown.load(Ordering::Relaxed) {
-                tracing::info!("File input shutdown requested");
-                return Ok(());
-            }
-            
-            // Pace to wall clock
-            let expected_time = start_time + frame_duration * frame_idx;
-            let now = Instant::now();
-            if now < expected_time {
-                std::thread::sleep(expected_time - now);
-            }
-            
-            if tx.send(frame).is_err() {
-                tracing::info!("File input: receiver dropped");
-                return Ok(());
-            }
-            
-            frame_idx += 1;
+    for frame in frames {
+        if shutdown.load(Ordering::Relaxed) {
+            tracing::info!("File input shutdown requested");
+            return Ok(());
         }
         
-        // Flush remaining
-        if let Some(frame) = resampler.flush()? {
-            if !shutdown.load(Ordering::Relaxed) {
-                let _ = tx.send(frame);
-            }
-        }
-    } else {
-        // No resampling needed, send directly
-        for chunk in pcm.chunks(FRAME_SIZE) {
-            if shutdown.load(Ordering::Relaxed) {
-                tracing::info!("File input shutdown requested");
-                return Ok(());
-            }
-            
-            if chunk.len() < FRAME_SIZE {
-                // Pad last frame
-                let mut frame = [0.0f32; FRAME_SIZE];
-                frame[..chunk.len()].copy_from_slice(chunk);
-                let _ = tx.send(frame);
-                break;
-            }
-            
-            let mut frame = [0.0f32; FRAME_SIZE];
-            frame.copy_from_slice(chunk);
-            
-            // Pace to wall clock
+        // Pace to wall clock
+        if pace {
             let expected_time = start_time + frame_duration * frame_idx;
             let now = Instant::now();
             if now < expected_time {
                 std::thread::sleep(expected_time - now);
             }
-            
-            if tx.send(frame).is_err() {
-                tracing::info!("File input: receiver dropped");
-                return Ok(());
-            }
-            
-            frame_idx += 1;
         }
+        
+        if tx.send(frame).is_err() {
+            tracing::info!("File input: receiver dropped");
+            return Ok(());
+        }
+        
+        frame_idx += 1;
     }
     
-    tracing::info!("File input complete: {} frames", frame_idx);
+    tracing::info!(
+        "File input complete: {} frames in {:.1}s",
+        frame_idx,
+        start_time.elapsed().as_secs_f32()
+    );
     Ok(())
 }
 
 /// Captures audio from a microphone and emits 80ms frames
+///
+/// When the stream dies, e.g. the device was unplugged, the device matching
+/// `query` is looked up again with `selector` and reopened with backoff. The
+/// capture ends with an error when that fails.
 pub fn run_mic_input(
     device: cpal::Device,
+    query: DeviceQuery,
+    selector: DeviceSelector,
+    channel: InputChannel,
     tx: mpsc::SyncSender<AudioFrame>,
     shutdown: Arc<std::sync::atomic::AtomicBool>,
+    events_tx: Option<mpsc::Sender<Event>>,
 ) -> Result<()> {
-    use std::sync::atomic::Ordering;
+    // Shared state between callback and main thread
+    let tx = Arc::new(FrameSender::new(tx));
+    let error_flag = Arc::new(Mutex::new(None::<String>));
+    let mut dropped_reported = 0;
+    let mut last_drop_report = Instant::now();
+    
+    let mut watchdog = Watchdog::new();
+    let mut stream = open_mic_stream(&device, channel, &tx, &error_flag, watchdog.probe())?;
+    let mut device_name = device.name().unwrap_or_else(|_| query.to_string());
+    tracing::info!("Microphone capture started");
+    
+    let send_event = |event: Event| {
+        if let Some(events_tx) = events_tx.as_ref() {
+            let _ = events_tx.send(event);
+        }
+    };
+    
+    // Keep stream alive until shutdown
+    while !shutdown.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(100));
+        
+        // Check for errors
+        if let Some(err) = error_flag.lock().unwrap().take() {
+            anyhow::bail!("Mic input error: {}", err);
+        }
+        
+        let dropped = tx.dropped();
+        if dropped > dropped_reported && last_drop_report.elapsed() >= Duration::from_secs(5) {
+            tracing::warn!("Model slower than real time, {} mic frames dropped so far", dropped);
+            dropped_reported = dropped;
+            last_drop_report = Instant::now();
+        }
+        
+        if watchdog.is_dead() {
+            tracing::error!("Microphone stream died, reopening '{}'", query);
+            drop(stream);
+            send_event(Event::DeviceLost {
+                direction: "input".to_string(),
+                device: device_name.clone(),
+            });
+            let reopened = recovery::retry_with_backoff(&shutdown, || {}, || {
+                let (device, _) = devices::find_input_device(&selector, &query)?;
+                let watchdog = Watchdog::new();
+                let stream = open_mic_stream(&device, channel, &tx, &error_flag, watchdog.probe())?;
+                let name = device.name().unwrap_or_else(|_| query.to_string());
+                Ok((stream, watchdog, name))
+            });
+            match reopened {
+                Ok(((new_stream, new_watchdog, name), attempts)) => {
+                    tracing::info!("Microphone capture recovered after {} attempts: {}", attempts, name);
+                    (stream, watchdog, device_name) = (new_stream, new_watchdog, name);
+                    send_event(Event::DeviceRecovered {
+                        direction: "input".to_string(),
+                        device: device_name.clone(),
+                        attempts,
+                    });
+                }
+                Err(_) if shutdown.load(Ordering::Relaxed) => return Ok(()),
+                Err(e) => {
+                    send_event(Event::DeviceFailed {
+                        direction: "input".to_string(),
+                        device: device_name,
+                        message: e.to_string(),
+                    });
+                    anyhow::bail!("Microphone lost and could not be reopened: {}", e);
+                }
+            }
+        }
+    }
     
+    drop(stream);
+    tracing::info!("Microphone capture stopped");
+    Ok(())
+}
+
+/// Opens and starts the capture stream in the default input config of the
+/// device
+fn open_mic_stream(
+    device: &cpal::Device,
+    channel: InputChannel,
+    tx: &Arc<FrameSender>,
+    error_flag: &Arc<Mutex<Option<String>>>,
+    probe: StreamProbe,
+) -> Result<cpal::Stream> {
     let config = device.default_input_config()
         .context("Failed to get default input config")?;
     
@@ -134,105 +220,91 @@ pub fn run_mic_input(
     let sample_rate = config.sample_rate().0 as usize;
     let channels = config.channels() as usize;
     
-    // Shared state between callback and main thread
     let resampler = Arc::new(Mutex::new(
-        StreamingResampler::new(sample_rate, channels)?
+        StreamingResampler::new(sample_rate, channels, channel)?
     ));
-    let tx = Arc::new(Mutex::new(tx));
-    let error_flag = Arc::new(Mutex::new(None::<String>));
     
-    let resampler_cb = resampler.clone();
-    let tx_cb = tx.clone();
-    let error_flag_cb = error_flag.clone();
-    
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => {
-            device.build_input_stream(
-                &config.into(),
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    if let Err(e) = handle_input_data(data, &resampler_cb, &tx_cb) {
-                        *error_flag_cb.lock().unwrap() = Some(e.to_string());
-                    }
-                },
-                move |err| {
-                    tracing::error!("Mic input stream error: {}", err);
-                },
-                None,
-            )?
-        }
-        cpal::SampleFormat::I16 => {
-            device.build_input_stream(
-                &config.into(),
-                move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                    let float_data: Vec<f32> = data.iter()
-                        .map(|&s| s as f32 / i16::MAX as f32)
-                        .collect();
-                    if let Err(e) = handle_input_data(&float_data, &resampler_cb, &tx_cb) {
-                        *error_flag_cb.lock().unwrap() = Some(e.to_string());
-                    }
-                },
-                move |err| {
-                    tracing::error!("Mic input stream error: {}", err);
-                },
-                None,
-            )?
-        }
-        cpal::SampleFormat::U16 => {
-            device.build_input_stream(
-                &config.into(),
-                move |data: &[u16], _: &cpal::InputCallbackInfo| {
-                    let float_data: Vec<f32> = data.iter()
-                        .map(|&s| (s as f32 / u16::MAX as f32) * 2.0 - 1.0)
-                        .collect();
-                    if let Err(e) = handle_input_data(&float_data, &resampler_cb, &tx_cb) {
-                        *error_flag_cb.lock().unwrap() = Some(e.to_string());
-                    }
-                },
-                move |err| {
-                    tracing::error!("Mic input stream error: {}", err);
-                },
-                None,
+    let sample_format = config.sample_format();
+    let config: cpal::StreamConfig = config.into();
+    macro_rules! build {
+        ($sample:ty) => {
+            build_input_stream::<$sample>(
+                device,
+                &config,
+                resampler,
+                tx.clone(),
+                error_flag.clone(),
+                probe,
             )?
-        }
-        _ => anyhow::bail!("Unsupported sample format: {:?}", config.sample_format()),
+        };
+    }
+    let stream = match sample_format {
+        cpal::SampleFormat::I8 => build!(i8),
+        cpal::SampleFormat::I16 => build!(i16),
+        cpal::SampleFormat::I32 => build!(i32),
+        cpal::SampleFormat::I64 => build!(i64),
+        cpal::SampleFormat::U8 => build!(u8),
+        cpal::SampleFormat::U16 => build!(u16),
+        cpal::SampleFormat::U32 => build!(u32),
+        cpal::SampleFormat::U64 => build!(u64),
+        cpal::SampleFormat::F32 => build!(f32),
+        cpal::SampleFormat::F64 => build!(f64),
+        _ => anyhow::bail!("Unsupported sample format: {:?}", sample_format),
     };
     
     stream.play()?;
-    tracing::info!("Microphone capture started");
-    
-    // Keep stream alive until shutdown
-    while !shutdown.load(Ordering::Relaxed) {
-        std::thread::sleep(Duration::from_millis(100));
-        
-        // Check for errors
-        if let Some(err) = error_flag.lock().unwrap().take() {
-            anyhow::bail!("Mic input error: {}", err);
-        }
-    }
-    
-    drop(stream);
-    tracing::info!("Microphone capture stopped");
-    Ok(())
+    Ok(stream)
+}
+
+/// Builds the capture stream for samples of type `T`, converted to f32 in
+/// [-1, 1] in a buffer reused across callbacks
+fn build_input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    resampler: Arc<Mutex<StreamingResampler>>,
+    tx: Arc<FrameSender>,
+    error_flag: Arc<Mutex<Option<String>>>,
+    probe: StreamProbe,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let mut float_data: Vec<f32> = Vec::new();
+    let probe_cb = probe.clone();
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            probe_cb.tick();
+            float_data.clear();
+            float_data.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+            if let Err(e) = handle_input_data(&float_data, &resampler, &tx) {
+                *error_flag.lock().unwrap() = Some(e.to_string());
+            }
+        },
+        move |err| probe.error("Mic input", err),
+        None,
+    )?;
+    Ok(stream)
 }
 
 fn handle_input_data(
     data: &[f32],
     resampler: &Arc<Mutex<StreamingResampler>>,
-    tx: &Arc<Mutex<mpsc::SyncSender<AudioFrame>>>,
+    tx: &Arc<FrameSender>,
 ) -> Result<()> {
     // Check if there's actual audio (not just silence)
     let rms = (data.iter().map(|s| s * s).sum::<f32>() / data.len() as f32).sqrt();
     
     let frames = resampler.lock().unwrap().push_samples(data)?;
     
-    let tx = tx.lock().unwrap();
     for frame in frames {
         // Log when we send frames (throttled by only logging when there's actual audio)
         if rms > 0.01 {
             tracing::debug!("📡 Mic captured: {} samples, RMS: {:.4}, sending frame to model", data.len(), rms);
         }
         
-        if tx.send(frame).is_err() {
+        if !tx.send(frame) {
             // Receiver dropped, that's ok
             return Ok(());
         }
@@ -240,3 +312,35 @@ fn handle_input_data(
     
     Ok(())
 }
+
+/// Hands the captured frames to the model from the audio callback.
+///
+/// The callback must never block, or the watchdog would take the stalled
+/// stream for a dead device: frames are dropped and counted when the model
+/// falls behind.
+struct FrameSender {
+    tx: Mutex<mpsc::SyncSender<AudioFrame>>,
+    dropped: AtomicU64,
+}
+
+impl FrameSender {
+    fn new(tx: mpsc::SyncSender<AudioFrame>) -> Self {
+        Self { tx: Mutex::new(tx), dropped: AtomicU64::new(0) }
+    }
+    
+    /// Returns false once the receiver is gone.
+    fn send(&self, frame: AudioFrame) -> bool {
+        match self.tx.lock().unwrap().try_send(frame) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        }
+    }
+    
+    fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -3,7 +3,8 @@
 // LICENSE file in the root directory of this source tree.
 
 use anyhow::Result;
-use candle::Device;
+use cpal::traits::DeviceTrait;
+use std::io::Write;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
@@ -11,49 +12,90 @@ use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::audio_writer::OutputConfig;
+use crate::events::{Event, EventWriter};
+
 mod devices;
-mod input;
-mod model;
+pub(crate) mod input;
+pub(crate) mod model;
 mod playback;
-mod resampler;
-mod wav_writer;
+pub mod processor;
+pub mod raw;
+pub mod recovery;
+pub mod resampler;
+pub mod segment;
+pub mod vad;
+mod writer;
 
-pub use devices::list_devices;
+pub use devices::{
+    choose_output_config, device_list, find_host, list_devices, select_device, ConfigInfo,
+    ConfigRangeInfo, DeviceInfo, DeviceList, DeviceQuery, DeviceSelector,
+};
+pub use model::ModelStats;
+pub use processor::{EchoProcessor, FrameProcessor, ToneProcessor};
+pub use raw::{RawInputFormat, RawSampleFormat};
+pub use resampler::InputChannel;
+pub use vad::{SilenceAction, VadConfig};
 
+#[derive(Debug, Clone, Default)]
 pub struct StreamConfig {
-    // Input source (exactly one)
+    // Input source (exactly one), "-" reads raw PCM from stdin. Devices are
+    // designated by their index in --list-devices or by name
     pub input_file: Option<PathBuf>,
     pub input_device: Option<String>,
     
+    // cpal host the devices are looked up in, the default one if None
+    pub audio_host: Option<String>,
+    
+    // Fail on ambiguous or unmatched device names instead of falling back
+    pub strict_devices: bool,
+    
+    // Layout of the raw PCM read from stdin
+    pub raw_input: RawInputFormat,
+    
+    // Channel fed to the model for multichannel inputs, all mixed by default
+    pub input_channel: InputChannel,
+    
+    // Feed file input as fast as the model consumes it instead of in real time
+    pub no_pace: bool,
+    
     // Output routing
     pub output_device: Option<String>,
     pub disable_speaker: bool,
     
-    // WAV saving
+    // Saving the generated audio, WAV, Ogg/Opus or FLAC
     pub save_output: Option<PathBuf>,
+    pub output: OutputConfig,
+    
+    // Recording of the 24kHz frames fed to the model, WAV, Ogg/Opus or FLAC
+    pub save_input: Option<PathBuf>,
+    
+    // Stereo WAV of the model input (left) and the generated audio (right),
+    // aligned by step
+    pub save_stereo: Option<PathBuf>,
+    
+    // Raw 24kHz mono PCM of the generated audio on stdout, the logs and the
+    // text then go to stderr
+    pub stdout_pcm: Option<RawSampleFormat>,
+    
+    // Subtitles of the generated text (.srt or .vtt)
+    pub subtitles: Option<PathBuf>,
+    
+    // JSON lines event stream, "-" for stdout
+    pub events: Option<PathBuf>,
     
-    // Model config
-    pub lm_config: moshi::lm::Config,
-    pub lm_model_file: PathBuf,
-    pub mimi_model_file: PathBuf,
-    pub text_tokenizer: PathBuf,
-    pub seed: u64,
-    pub cfg_alpha: Option<f64>,
+    // Voice activity detection on the input, marking the utterances and
+    // pausing the model during long silences
+    pub vad: Option<VadConfig>,
 }
 
 struct Metrics {
-    frames_captured: Arc<AtomicU64>,
-    frames_processed: Arc<AtomicU64>,
     last_log_time: Instant,
 }
 
-use std::sync::atomic::AtomicU64;
-
 impl Metrics {
     fn new() -> Self {
         Self {
-            frames_captured: Arc::new(AtomicU64::new(0)),
-            frames_processed: Arc::new(AtomicU64::new(0)),
             last_log_time: Instant::now(),
         }
     }
@@ -68,28 +110,81 @@ impl Metrics {
     }
 }
 
-pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
+/// Runs the streaming pipeline until the input ends or Ctrl-C is pressed.
+pub fn run<P: FrameProcessor + Send + 'static>(config: StreamConfig, processor: P) -> Result<()> {
+    // Setup shutdown signal
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_ctrlc = shutdown.clone();
+    ctrlc::set_handler(move || {
+        tracing::info!("Shutdown signal received");
+        shutdown_ctrlc.store(true, Ordering::Relaxed);
+    })?;
+
+    run_with_shutdown(config, processor, shutdown)
+}
+
+/// Same as [`run`] but stops when `shutdown` is set instead of installing a
+/// Ctrl-C handler, so that the caller controls the lifetime of the session.
+pub fn run_with_shutdown<P: FrameProcessor + Send + 'static>(
+    config: StreamConfig,
+    processor: P,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
     // Validate input
     match (&config.input_file, &config.input_device) {
         (Some(_), Some(_)) => anyhow::bail!("Specify either --input-file or --input-device, not both"),
         (None, None) => anyhow::bail!("Must specify either --input-file or --input-device"),
         _ => {}
     }
+    if config.save_output.is_some() {
+        config.output.validate()?;
+    }
+    if let Some(ref vad) = config.vad {
+        vad.validate()?;
+    }
+    let stdin_input = config.input_file.as_deref().is_some_and(raw::is_stdin);
+    if stdin_input {
+        config.raw_input.validate()?;
+    }
+    let events_to_stdout = config.events.as_deref().is_some_and(crate::events::is_stdout);
+    if config.stdout_pcm.is_some() && events_to_stdout {
+        anyhow::bail!("--stdout-pcm and --events - cannot both write to stdout");
+    }
+    if config.no_pace {
+        if config.input_file.is_none() {
+            anyhow::bail!("--no-pace only applies to --input-file");
+        }
+        if !config.disable_speaker {
+            anyhow::bail!("--no-pace cannot be used with speaker output, add --disable-speaker");
+        }
+    }
+    
+    let input_query = config.input_device.as_deref().map(str::parse::<DeviceQuery>).transpose()?;
+    let output_query = config.output_device.as_deref().map(str::parse::<DeviceQuery>).transpose()?;
+    let selector = DeviceSelector::new(config.audio_host.as_deref(), config.strict_devices)?;
     
     // Log configuration
     tracing::info!("=== Hibiki Streaming Configuration ===");
     if let Some(ref path) = config.input_file {
-        tracing::info!("Input: File '{}'", path.display());
-    } else if let Some(ref dev) = config.input_device {
-        tracing::info!("Input: Microphone '{}'", dev);
+        let pacing = if config.no_pace { "unpaced" } else { "real time" };
+        if stdin_input {
+            tracing::info!("Input: Raw PCM on stdin, {} ({})", config.raw_input, pacing);
+        } else {
+            tracing::info!("Input: File '{}' ({})", path.display(), pacing);
+        }
+    } else if let Some(ref dev) = input_query {
+        tracing::info!("Input: Microphone '{}' ({})", dev, selector.host.name());
+    }
+    if let InputChannel::Index(index) = config.input_channel {
+        tracing::info!("Input channel: {}", index);
     }
     
     if config.disable_speaker {
         tracing::info!("Output: Speaker disabled");
-    } else if let Some(ref dev) = config.output_device {
-        tracing::info!("Output: Speaker '{}'", dev);
+    } else if let Some(ref dev) = output_query {
+        tracing::info!("Output: Speaker '{}' ({})", dev, selector.host.name());
     } else {
-        tracing::info!("Output: Default speaker");
+        tracing::info!("Output: Default speaker ({})", selector.host.name());
     }
     
     if let Some(ref path) = config.save_output {
@@ -97,237 +192,288 @@ pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
     } else {
         tracing::info!("Save to: (none)");
     }
-    
-    // Setup shutdown signal
-    let shutdown = Arc::new(AtomicBool::new(false));
-    let shutdown_ctrlc = shutdown.clone();
-    ctrlc::set_handler(move || {
-        tracing::info!("Shutdown signal received");
-        shutdown_ctrlc.store(true, Ordering::Relaxed);
-    })?;
+    if let Some(ref path) = config.save_input {
+        tracing::info!("Input recording: {}", path.display());
+    }
+    if let Some(ref path) = config.save_stereo {
+        tracing::info!("Stereo recording: {}", path.display());
+    }
+    if let Some(format) = config.stdout_pcm {
+        tracing::info!("Stdout: Raw {} PCM at 24 kHz", format);
+    }
+    if let Some(ref vad) = config.vad {
+        tracing::info!(
+            "VAD: {} dBFS, {}ms hangover, {}ms long silence ({})",
+            vad.threshold_db,
+            vad.hangover.as_millis(),
+            vad.long_silence.as_millis(),
+            vad.on_long_silence
+        );
+    }
+    if let Some(ref path) = config.subtitles {
+        tracing::info!("Subtitles: {}", path.display());
+    }
+    if let Some(ref path) = config.events {
+        tracing::info!("Events: {}", path.display());
+    }
     
     // Create channels
     let (capture_tx, capture_rx) = mpsc::sync_channel::<[f32; resampler::FRAME_SIZE]>(50);
     let (audio_tx, audio_rx) = mpsc::sync_channel::<Vec<f32>>(50);
-    let (text_tx, text_rx) = mpsc::channel::<String>();
+    let (text_tx, text_rx) = mpsc::channel::<model::TextUpdate>();
+    
+    // Start events writer thread
+    let (events_tx, events_handle) = match config.events {
+        Some(ref path) => {
+            let mut writer = EventWriter::create(path)?;
+            let (events_tx, events_rx) = mpsc::channel::<Event>();
+            events_tx.send(Event::session_start("stream", processor.info()))?;
+            let handle = thread::Builder::new()
+                .name("events".to_string())
+                .spawn(move || {
+                    while let Ok(event) = events_rx.recv() {
+                        if let Err(e) = writer.emit(&event) {
+                            tracing::error!("Failed to write events: {}", e);
+                            break;
+                        }
+                    }
+                })?;
+            (Some(events_tx), Some(handle))
+        }
+        None => (None, None),
+    };
     
     // Start capture thread
     let shutdown_capture = shutdown.clone();
-    let capture_handle = if let Some(ref path) = config.input_file {
+    let channel = config.input_channel;
+    let capture_handle = if stdin_input {
+        let format = config.raw_input;
+        let pace = !config.no_pace;
+        thread::Builder::new()
+            .name("capture-stdin".to_string())
+            .spawn(move || {
+                let stdin = std::io::stdin();
+                raw::run_raw_input(stdin, &format, channel, pace, capture_tx, shutdown_capture)
+            })?
+    } else if let Some(ref path) = config.input_file {
         let path = path.clone();
+        let pace = !config.no_pace;
         thread::Builder::new()
             .name("capture-file".to_string())
-            .spawn(move || input::run_file_input(&path, capture_tx, shutdown_capture))?
-    } else if let Some(ref dev_name) = config.input_device {
-        let device = devices::find_input_device(dev_name)?;
+            .spawn(move || {
+                input::run_file_input(&path, channel, pace, capture_tx, shutdown_capture)
+            })?
+    } else if let Some(ref query) = input_query {
+        let (device, query) = devices::find_input_device(&selector, query)?;
+        let events_capture = events_tx.clone();
         thread::Builder::new()
             .name("capture-mic".to_string())
-            .spawn(move || input::run_mic_input(device, capture_tx, shutdown_capture))?
+            .spawn(move || {
+                let (tx, shutdown) = (capture_tx, shutdown_capture);
+                input::run_mic_input(device, query, selector, channel, tx, shutdown, events_capture)
+            })?
     } else {
         unreachable!()
     };
     
-    // Setup audio routing based on configuration
-    let (playback_handle, wav_handle) = if config.save_output.is_some() && !config.disable_speaker {
-        // Both playback and WAV: need to tee the audio
+    // Setup audio routing, every sink gets its own copy of the generated audio
+    let mut sinks = Vec::new();
+    let playback_handle = if !config.disable_speaker {
         let (playback_tx, playback_rx) = mpsc::sync_channel::<Vec<f32>>(50);
-        let (wav_tx, wav_rx) = mpsc::sync_channel::<Vec<f32>>(50);
-        
-        // Tee thread: receives from model, sends to both playback and WAV
-        thread::Builder::new()
-            .name("audio-tee".to_string())
-            .spawn(move || {
-                while let Ok(samples) = audio_rx.recv() {
-                    let _ = playback_tx.send(samples.clone());
-                    let _ = wav_tx.send(samples);
-                }
-            })?;
-        
-        // Playback thread
-        let device = devices::find_output_device(config.output_device.as_deref())?;
+        sinks.push(playback_tx);
+        let (device, query) = devices::find_output_device(&selector, output_query.as_ref())?;
         let shutdown_playback = shutdown.clone();
+        let events_playback = events_tx.clone();
         let playback_h = thread::Builder::new()
             .name("playback".to_string())
             .spawn(move || {
-                let mut sink = match playback::SpeakerSink::new(device) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        tracing::error!("Failed to create speaker sink: {}", e);
-                        return (0, 0, 0);
-                    }
-                };
-                
-                loop {
-                    match playback_rx.recv_timeout(Duration::from_millis(100)) {
-                        Ok(samples) => {
-                            if let Err(e) = sink.push_samples(&samples) {
-                                tracing::error!("Playback error: {}", e);
-                                break;
-                            }
-                        }
-                        Err(mpsc::RecvTimeoutError::Timeout) => {
-                            // Check if we should exit (only after channel closed)
-                            if shutdown_playback.load(Ordering::Relaxed) {
-                                tracing::info!("Playback thread: shutdown requested, {} samples in buffer", sink.buffer_level());
-                                break;
-                            }
-                            continue;
-                        }
-                        Err(mpsc::RecvTimeoutError::Disconnected) => {
-                            tracing::info!("Input ended, draining {} samples from buffer...", sink.buffer_level());
-                            break;
-                        }
-                    }
-                }
-                
-                // CRITICAL: Wait for buffered audio to finish playing
-                let buffer_level = sink.buffer_level();
-                if buffer_level > 0 {
-                    let drain_seconds = buffer_level as f64 / 24000.0;
-                    tracing::info!("Waiting {:.1}s for remaining audio to play out...", drain_seconds);
-                    thread::sleep(Duration::from_secs_f64(drain_seconds + 0.5)); // +0.5s safety margin
-                }
-                
-                (sink.underrun_count(), sink.overflow_count(), sink.buffer_level())
+                let (rx, shutdown) = (playback_rx, shutdown_playback);
+                run_playback(device, selector, query, rx, shutdown, events_playback)
             })?;
-        
-        // WAV writer thread
-        let path = config.save_output.as_ref().unwrap().clone();
-        let wav_h = thread::Builder::new()
-            .name("wav-writer".to_string())
-            .spawn(move || wav_writer::run_wav_writer(&path, wav_rx))?;
-        
-        (Some(playback_h), Some(wav_h))
-    } else if !config.disable_speaker {
-        // Playback only, no WAV
-        let device = devices::find_output_device(config.output_device.as_deref())?;
-        let shutdown_playback = shutdown.clone();
-        
-        let playback_h = thread::Builder::new()
-            .name("playback".to_string())
-            .spawn(move || {
-                let mut sink = match playback::SpeakerSink::new(device) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        tracing::error!("Failed to create speaker sink: {}", e);
-                        return (0, 0, 0);
-                    }
-                };
-                
-                loop {
-                    match audio_rx.recv_timeout(Duration::from_millis(100)) {
-                        Ok(samples) => {
-                            if let Err(e) = sink.push_samples(&samples) {
-                                tracing::error!("Playback error: {}", e);
-                                break;
-                            }
-                        }
-                        Err(mpsc::RecvTimeoutError::Timeout) => {
-                            // Check if we should exit (only after channel closed)
-                            if shutdown_playback.load(Ordering::Relaxed) {
-                                tracing::info!("Playback thread: shutdown requested, {} samples in buffer", sink.buffer_level());
-                                break;
-                            }
-                            continue;
-                        }
-                        Err(mpsc::RecvTimeoutError::Disconnected) => {
-                            tracing::info!("Input ended, draining {} samples from buffer...", sink.buffer_level());
+        Some(playback_h)
+    } else {
+        None
+    };
+    
+    let mut writer_handles = Vec::new();
+    if let Some(ref path) = config.save_output {
+        let (writer_tx, writer_rx) = mpsc::sync_channel::<Vec<f32>>(50);
+        sinks.push(writer_tx);
+        let path = path.clone();
+        let output = config.output.clone();
+        let writer_h = thread::Builder::new()
+            .name("audio-writer".to_string())
+            .spawn(move || writer::run_audio_writer(&path, &output, writer_rx))?;
+        writer_handles.push(writer_h);
+    }
+    if let Some(format) = config.stdout_pcm {
+        let (stdout_tx, stdout_rx) = mpsc::sync_channel::<Vec<f32>>(50);
+        sinks.push(stdout_tx);
+        let stdout_h = thread::Builder::new()
+            .name("audio-stdout".to_string())
+            .spawn(move || raw::run_raw_output(std::io::stdout(), format, stdout_rx))?;
+        writer_handles.push(stdout_h);
+    }
+    
+    let steps_tx = match config.save_stereo {
+        Some(ref path) => {
+            let (steps_tx, steps_rx) = mpsc::channel();
+            let path = path.clone();
+            let stereo_h = thread::Builder::new()
+                .name("stereo-writer".to_string())
+                .spawn(move || writer::run_stereo_writer(&path, steps_rx))?;
+            writer_handles.push(stereo_h);
+            Some(steps_tx)
+        }
+        None => None,
+    };
+    
+    // Input tee thread: records the captured frames on their way to the model
+    let capture_rx = match config.save_input {
+        Some(ref path) => {
+            let (input_tx, input_rx) = mpsc::channel::<Vec<f32>>();
+            let path = path.clone();
+            let input_h = thread::Builder::new()
+                .name("input-writer".to_string())
+                .spawn(move || {
+                    writer::run_audio_writer(&path, &OutputConfig::default(), input_rx)
+                })?;
+            writer_handles.push(input_h);
+            let (model_tx, model_rx) = mpsc::sync_channel::<[f32; resampler::FRAME_SIZE]>(50);
+            thread::Builder::new()
+                .name("input-tee".to_string())
+                .spawn(move || {
+                    while let Ok(frame) = capture_rx.recv() {
+                        // The recording is unbounded so that no frame is lost.
+                        let _ = input_tx.send(frame.to_vec());
+                        if model_tx.send(frame).is_err() {
                             break;
                         }
                     }
-                }
-                
-                // CRITICAL: Wait for buffered audio to finish playing
-                let buffer_level = sink.buffer_level();
-                if buffer_level > 0 {
-                    let drain_seconds = buffer_level as f64 / 24000.0;
-                    tracing::info!("Waiting {:.1}s for remaining audio to play out...", drain_seconds);
-                    thread::sleep(Duration::from_secs_f64(drain_seconds + 0.5)); // +0.5s safety margin
-                }
-                
-                (sink.underrun_count(), sink.overflow_count(), sink.buffer_level())
-            })?;
-        
-        (Some(playback_h), None)
-    } else if let Some(ref path) = config.save_output {
-        // WAV only, no playback
-        let path = path.clone();
-        let wav_h = thread::Builder::new()
-            .name("wav-writer".to_string())
-            .spawn(move || wav_writer::run_wav_writer(&path, audio_rx))?;
-        
-        (None, Some(wav_h))
-    } else {
-        // Neither playback nor WAV - just drain
-        thread::Builder::new()
-            .name("audio-drain".to_string())
-            .spawn(move || {
-                while audio_rx.recv().is_ok() {}
-            })?;
-        
-        (None, None)
+                })?;
+            model_rx
+        }
+        None => capture_rx,
     };
     
-    // Start text printer thread
+    // Tee thread: receives from model, sends to every sink, or just drains
+    // when there are none
+    thread::Builder::new()
+        .name("audio-tee".to_string())
+        .spawn(move || {
+            while let Ok(samples) = audio_rx.recv() {
+                // A sink that stopped does not stop the others.
+                sinks.retain(|sink| sink.send(samples.clone()).is_ok());
+            }
+        })?;
+    
+    // Start text printer thread, also writing the subtitles if requested
+    let mut subtitles = match config.subtitles {
+        Some(ref path) => Some(crate::subtitles::SubtitleWriter::create(path)?),
+        None => None,
+    };
+    // Keep stdout clean when the events or the audio are written there.
+    let mut text_out: Option<Box<dyn Write + Send>> = if events_to_stdout {
+        None
+    } else if config.stdout_pcm.is_some() {
+        Some(Box::new(std::io::stderr()))
+    } else {
+        Some(Box::new(std::io::stdout()))
+    };
     let text_handle = thread::Builder::new()
         .name("text-printer".to_string())
         .spawn(move || {
-            use std::io::Write;
-            while let Ok(text) = text_rx.recv() {
-                print!("{}", text);
-                std::io::stdout().flush().unwrap();
+            while let Ok(update) = text_rx.recv() {
+                let result = match update {
+                    model::TextUpdate::Text(timed) => {
+                        if let Some(out) = text_out.as_mut() {
+                            let _ = write!(out, "{}", timed.text);
+                            let _ = out.flush();
+                        }
+                        subtitles.as_mut().map(|w| w.push(timed.sample, &timed.text))
+                    }
+                    model::TextUpdate::Cut => {
+                        if let Some(out) = text_out.as_mut() {
+                            let _ = writeln!(out);
+                        }
+                        subtitles.as_mut().map(|w| w.cut())
+                    }
+                };
+                if let Some(Err(e)) = result {
+                    tracing::error!("Failed to write subtitles: {}", e);
+                    subtitles = None;
+                }
+            }
+            if let Some(out) = text_out.as_mut() {
+                let _ = writeln!(out); // Final newline
+            }
+            if let Some(writer) = subtitles {
+                if let Err(e) = writer.finish() {
+                    tracing::error!("Failed to write subtitles: {}", e);
+                }
             }
-            println!(); // Final newline
         })?;
     
-    // Load and run model
-    tracing::info!("Loading models...");
-    let model = model::StreamingModel::new(
-        &config.lm_config,
-        &config.lm_model_file,
-        &config.mimi_model_file,
-        &config.text_tokenizer,
-        config.seed,
-        config.cfg_alpha,
-        device,
-    )?;
-    
+    // Run model
     tracing::info!("Starting inference...");
     let shutdown_model = shutdown.clone();
+    // Unpaced input runs faster than real time, so the model has to wait for
+    // the writers instead of dropping audio.
+    let backpressure = config.no_pace;
+    let vad = config.vad;
+    let events_model = events_tx.clone();
     let model_handle = thread::Builder::new()
         .name("model".to_string())
         .spawn(move || {
-            model::run_model_thread(model, capture_rx, audio_tx, text_tx, shutdown_model)
+            let outputs = model::ModelOutputs {
+                audio_tx,
+                text_tx,
+                events_tx: events_model,
+                steps_tx,
+            };
+            model::run_model_thread(
+                processor,
+                capture_rx,
+                outputs,
+                vad,
+                backpressure,
+                shutdown_model,
+            )
         })?;
     
     // Monitoring loop
     let mut metrics = Metrics::new();
-    let mut last_underruns = 0u64;
-    let mut last_overflows = 0u64;
     
     while !shutdown.load(Ordering::Relaxed) {
         thread::sleep(Duration::from_millis(500));
         
-        if metrics.should_log() {
-            if let Some(ref handle) = playback_handle {
-                // Note: We can't easily get live stats without more complex IPC
-                // For now, just log that we're running
-                tracing::info!("Streaming active...");
-            }
+        if metrics.should_log() && playback_handle.is_some() {
+            // Note: We can't easily get live stats without more complex IPC
+            // For now, just log that we're running
+            tracing::info!("Streaming active...");
         }
     }
     
     // Shutdown sequence
     tracing::info!("Shutting down...");
     
-    // Wait for capture to finish
-    if let Err(e) = capture_handle.join() {
-        tracing::error!("Capture thread panicked: {:?}", e);
-    }
+    // Wait for capture to finish, its error ends the session once the other
+    // threads are done
+    let capture_error = match capture_handle.join() {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            tracing::error!("Capture thread error: {}", e);
+            Some(e)
+        }
+        Err(e) => {
+            tracing::error!("Capture thread panicked: {:?}", e);
+            None
+        }
+    };
     
     // Wait for model to finish
     let model_stats = match model_handle.join() {
-        Ok(Ok(stats)) => Some(stats),
+        Ok(Ok(result)) => Some(result),
         Ok(Err(e)) => {
             tracing::error!("Model thread error: {}", e);
             None
@@ -339,6 +485,7 @@ pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
     };
     
     // Wait for playback
+    let mut playback_stats = None;
     if let Some(handle) = playback_handle {
         match handle.join() {
             Ok((underruns, overflows, buffer_level)) => {
@@ -348,6 +495,7 @@ pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
                     overflows,
                     buffer_level
                 );
+                playback_stats = Some((underruns, overflows));
             }
             Err(e) => {
                 tracing::error!("Playback thread panicked: {:?}", e);
@@ -355,10 +503,12 @@ pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
         }
     }
     
-    // Wait for WAV writer
-    if let Some(handle) = wav_handle {
-        if let Err(e) = handle.join() {
-            tracing::error!("WAV writer thread error: {:?}", e);
+    // Wait for the audio writers
+    for handle in writer_handles {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!("Audio writer error: {}", e),
+            Err(e) => tracing::error!("Audio writer thread panicked: {:?}", e),
         }
     }
     
@@ -368,15 +518,152 @@ pub fn run(config: StreamConfig, device: &Device) -> Result<()> {
     }
     
     // Print final stats
-    if let Some(stats) = model_stats {
+    if let Some((stats, samples_generated)) = model_stats {
         tracing::info!(
             "Model stats: {} frames processed, avg {:.1}ms/frame, p95 {:.1}ms/frame",
             stats.frames_processed,
             stats.avg_time_ms,
             stats.p95_time_ms
         );
+        if let Some(ref events_tx) = events_tx {
+            let _ = events_tx.send(Event::session_end(&stats, samples_generated, playback_stats));
+        }
+    }
+    
+    // Wait for the events writer, it stops once every sender is gone
+    drop(events_tx);
+    if let Some(handle) = events_handle {
+        if let Err(e) = handle.join() {
+            tracing::error!("Events thread panicked: {:?}", e);
+        }
     }
     
+    if let Some(e) = capture_error {
+        return Err(e.context("the audio input failed"));
+    }
     tracing::info!("Streaming complete");
     Ok(())
 }
+
+/// Plays the generated audio until the model stops, then waits for the buffer
+/// to play out. Returns the underrun and overflow counts and the final buffer
+/// level.
+///
+/// When the speaker dies, the output device matching `query` (the default one
+/// if None) is looked up again with `selector` and reopened with backoff, the
+/// playback stops if that fails.
+fn run_playback(
+    device: cpal::Device,
+    selector: DeviceSelector,
+    query: Option<DeviceQuery>,
+    audio_rx: mpsc::Receiver<Vec<f32>>,
+    shutdown: Arc<AtomicBool>,
+    events_tx: Option<mpsc::Sender<Event>>,
+) -> (u64, u64, usize) {
+    let mut device_name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
+    let mut sink = match playback::SpeakerSink::new(device) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Failed to create speaker sink: {}", e);
+            return (0, 0, 0);
+        }
+    };
+    
+    // Underruns happen in the audio callback, report them as they are noticed.
+    let (mut underruns, mut overflows) = (0, 0);
+    let mut report = |sink: &playback::SpeakerSink| {
+        if let Some(events_tx) = events_tx.as_ref() {
+            if sink.underrun_count() > underruns {
+                underruns = sink.underrun_count();
+                let _ = events_tx.send(Event::Underrun { count: underruns });
+            }
+            if sink.overflow_count() > overflows {
+                overflows = sink.overflow_count();
+                let _ = events_tx.send(Event::Overflow { count: overflows });
+            }
+        }
+    };
+    
+    let send_event = |event: Event| {
+        if let Some(events_tx) = events_tx.as_ref() {
+            let _ = events_tx.send(event);
+        }
+    };
+    
+    loop {
+        if sink.is_dead() {
+            tracing::error!("Speaker stream died, reopening the output device");
+            send_event(Event::DeviceLost {
+                direction: "output".to_string(),
+                device: device_name.clone(),
+            });
+            // The audio generated in the meantime is dropped, not queued up.
+            let drain = || while audio_rx.try_recv().is_ok() {};
+            let reopened = recovery::retry_with_backoff(&shutdown, drain, || {
+                let (device, _) = devices::find_output_device(&selector, query.as_ref())?;
+                let name = device.name().unwrap_or_else(|_| "(unknown)".to_string());
+                Ok((sink.reopen(device)?, name))
+            });
+            match reopened {
+                Ok(((new_sink, name), attempts)) => {
+                    tracing::info!("Speaker output recovered after {} attempts: {}", attempts, name);
+                    (sink, device_name) = (new_sink, name);
+                    send_event(Event::DeviceRecovered {
+                        direction: "output".to_string(),
+                        device: device_name.clone(),
+                        attempts,
+                    });
+                }
+                Err(e) => {
+                    if !shutdown.load(Ordering::Relaxed) {
+                        tracing::error!("Speaker lost and could not be reopened, playback stopped: {}", e);
+                        send_event(Event::DeviceFailed {
+                            direction: "output".to_string(),
+                            device: device_name,
+                            message: e.to_string(),
+                        });
+                    }
+                    report(&sink);
+                    return (sink.underrun_count(), sink.overflow_count(), 0);
+                }
+            }
+        }
+        
+        match audio_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(samples) => {
+                if let Err(e) = sink.push_samples(&samples) {
+                    tracing::error!("Playback error: {}", e);
+                    break;
+                }
+                report(&sink);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                report(&sink);
+                // Check if we should exit (only after channel closed)
+                if shutdown.load(Ordering::Relaxed) {
+                    tracing::info!("Playback thread: shutdown requested, {} samples in buffer", sink.buffer_level());
+                    break;
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                if let Err(e) = sink.flush() {
+                    tracing::error!("Playback error: {}", e);
+                }
+                tracing::info!("Input ended, draining {} samples from buffer...", sink.buffer_level());
+                break;
+            }
+        }
+    }
+    
+    // CRITICAL: Wait for buffered audio to finish playing
+    let buffer_level = sink.buffer_level();
+    if buffer_level > 0 {
+        let drain_seconds = buffer_level as f64 / 24000.0;
+        tracing::info!("Waiting {:.1}s for remaining audio to play out...", drain_seconds);
+        thread::sleep(Duration::from_secs_f64(drain_seconds + 0.5)); // +0.5s safety margin
+    }
+    report(&sink);
+    
+    (sink.underrun_count(), sink.overflow_count(), sink.buffer_level())
+}
@@ -2,23 +2,47 @@
 // This source code is licensed under the license found in the
 // LICENSE file in the root directory of this source tree.
 
-use anyhow::Result;
-use candle::{Device, IndexOp, Tensor};
 use std::sync::mpsc;
 use std::time::Instant;
 
+use anyhow::Result;
+use candle::{Device, IndexOp, Tensor};
+
+use crate::events::{Event, FrameOutput, ModelInfo};
+use crate::sampling::SamplingConfig;
+
+use super::processor::FrameProcessor;
 use super::resampler::FRAME_SIZE;
+use super::segment::{rms, SegmentEnd, Segmenter, QUIET_RMS};
+use super::vad::{SilenceAction, Vad, VadConfig, VadEvent};
+
+/// Processing times kept for the stats, at least the last 10 minutes.
+const FRAME_TIMES_WINDOW: usize = 7500;
 
 pub struct StreamingModel {
     mimi: moshi::mimi::Mimi,
+    // Freshly loaded lm with empty caches, cloned whenever a new state is needed.
+    lm_model: moshi::lm::LmModel,
     state: moshi::lm_generate_multistream::State,
-    text_tokenizer: sentencepiece::SentencePieceProcessor,
+    state_config: moshi::lm_generate_multistream::Config,
+    sampling: SamplingConfig,
+    cfg_alpha: Option<f64>,
+    text_tokenizer: std::sync::Arc<sentencepiece::SentencePieceProcessor>,
     text_start_token: u32,
     prev_text_token: u32,
     generated_audio_codebooks: usize,
     device: Device,
     frame_times: Vec<f32>,
+    frames_processed: usize,
     conditions: Option<moshi::conditioner::Condition>,
+    info: ModelInfo,
+    // Text tokens decoded during the last frame, with their pieces.
+    last_text_tokens: Vec<(u32, String)>,
+    // Sessions are split in segments that each use a fresh state, see Segmenter.
+    segmenter: Segmenter,
+    segment_idx: u64,
+    // Output silence while a new segment warms up to keep the audio continuous.
+    pad_output: bool,
 }
 
 impl StreamingModel {
@@ -27,10 +51,11 @@ impl StreamingModel {
         lm_model_file: &std::path::Path,
         mimi_model_file: &std::path::Path,
         text_tokenizer_file: &std::path::Path,
-        seed: u64,
+        sampling: &SamplingConfig,
         cfg_alpha: Option<f64>,
         device: &Device,
     ) -> Result<Self> {
+        sampling.validate()?;
         let dtype = device.bf16_default_to_f32();
         
         tracing::info!("Loading language model...");
@@ -44,16 +69,7 @@ impl StreamingModel {
         )?;
         
         tracing::info!("Loading text tokenizer...");
-        let text_tokenizer = sentencepiece::SentencePieceProcessor::open(text_tokenizer_file)?;
-        
-        let audio_lp = candle_transformers::generation::LogitsProcessor::from_sampling(
-            seed,
-            candle_transformers::generation::Sampling::TopK { k: 250, temperature: 0.8 },
-        );
-        let text_lp = candle_transformers::generation::LogitsProcessor::from_sampling(
-            seed,
-            candle_transformers::generation::Sampling::TopK { k: 25, temperature: 0.8 },
-        );
+        let text_tokenizer = std::sync::Arc::new(sentencepiece::SentencePieceProcessor::open(text_tokenizer_file)?);
         
         let generated_audio_codebooks = lm_config.depformer.as_ref().map_or(8, |v| v.num_slices);
         
@@ -75,7 +91,7 @@ impl StreamingModel {
         let cfg_alpha = if cfg_alpha == Some(1.) { None } else { cfg_alpha };
         let text_start_token = lm_config.text_out_vocab_size as u32;
         
-        let config = moshi::lm_generate_multistream::Config {
+        let state_config = moshi::lm_generate_multistream::Config {
             acoustic_delay: 2,
             audio_vocab_size: lm_config.audio_vocab_size,
             generated_audio_codebooks,
@@ -85,32 +101,129 @@ impl StreamingModel {
             text_pad_token: 3,
         };
         
-        let state = moshi::lm_generate_multistream::State::new(
-            lm_model,
-            2500, // max steps
-            audio_lp,
-            text_lp,
-            None,
-            None,
+        let segmenter = Segmenter::new(lm_config.transformer.max_seq_len);
+        let state = new_state(lm_model.clone(), &state_config, sampling, 0, &segmenter, cfg_alpha);
+        
+        let device_name = match device {
+            Device::Cpu => "cpu",
+            Device::Cuda(_) => "cuda",
+            Device::Metal(_) => "metal",
+        };
+        let info = ModelInfo {
+            name: lm_model_file.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+            lm_model_file: Some(lm_model_file.to_path_buf()),
+            mimi_model_file: Some(mimi_model_file.to_path_buf()),
+            text_tokenizer: Some(text_tokenizer_file.to_path_buf()),
+            sampling: Some(sampling.clone()),
             cfg_alpha,
-            config,
-        );
+            device: Some(device_name.to_string()),
+        };
         
         tracing::info!("Models loaded successfully");
         
         Ok(Self {
             mimi,
+            lm_model,
             state,
+            state_config,
+            sampling: sampling.clone(),
+            cfg_alpha,
             text_tokenizer,
             text_start_token,
             prev_text_token: text_start_token,
             generated_audio_codebooks,
             device: device.clone(),
             frame_times: Vec::new(),
+            frames_processed: 0,
             conditions,
+            info,
+            last_text_tokens: Vec::new(),
+            segmenter,
+            segment_idx: 0,
+            pad_output: false,
         })
     }
     
+    /// Starts a new session without reloading the weights: clears the mimi
+    /// encoder and decoder state, re-creates the generation state (lm caches
+    /// and sampling rngs), goes back to the text start token and clears the
+    /// stats.
+    pub fn reset(&mut self) {
+        self.segment_idx = 0;
+        self.segmenter.reset();
+        self.pad_output = false;
+        self.reset_state();
+        self.last_text_tokens.clear();
+        self.frame_times.clear();
+        self.frames_processed = 0;
+    }
+    
+    /// Creates an independent session that shares the weights of this model,
+    /// as if it had just been loaded.
+    pub fn fork(&self) -> Self {
+        let mut mimi = self.mimi.clone();
+        mimi.reset_state();
+        let mut segmenter = self.segmenter.clone();
+        segmenter.reset();
+        let state = new_state(
+            self.lm_model.clone(),
+            &self.state_config,
+            &self.sampling,
+            0,
+            &segmenter,
+            self.cfg_alpha,
+        );
+        Self {
+            mimi,
+            lm_model: self.lm_model.clone(),
+            state,
+            state_config: self.state_config.clone(),
+            sampling: self.sampling.clone(),
+            cfg_alpha: self.cfg_alpha,
+            text_tokenizer: self.text_tokenizer.clone(),
+            text_start_token: self.text_start_token,
+            prev_text_token: self.text_start_token,
+            generated_audio_codebooks: self.generated_audio_codebooks,
+            device: self.device.clone(),
+            frame_times: Vec::new(),
+            frames_processed: 0,
+            conditions: self.conditions.clone(),
+            info: self.info.clone(),
+            last_text_tokens: Vec::new(),
+            segmenter,
+            segment_idx: 0,
+            pad_output: false,
+        }
+    }
+    
+    fn reset_state(&mut self) {
+        self.mimi.reset_state();
+        self.state = new_state(
+            self.lm_model.clone(),
+            &self.state_config,
+            &self.sampling,
+            self.segment_idx,
+            &self.segmenter,
+            self.cfg_alpha,
+        );
+        self.prev_text_token = self.text_start_token;
+    }
+    
+    /// Continues the session on a fresh state, the caches being full or about to.
+    fn next_segment(&mut self, end: SegmentEnd) {
+        match end {
+            SegmentEnd::Pause => tracing::info!(segment = self.segment_idx, "pause, starting a new segment"),
+            SegmentEnd::Limit => tracing::warn!(
+                segment = self.segment_idx,
+                "no pause in {} steps, starting a new segment",
+                self.segmenter.max_steps()
+            ),
+        }
+        self.segment_idx += 1;
+        self.pad_output = true;
+        self.reset_state();
+    }
+    
     /// Process one 80ms frame (1920 samples) and return generated audio + text
     pub fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)> {
         let start = Instant::now();
@@ -123,6 +236,7 @@ impl StreamingModel {
         
         let mut out_pcm = Vec::new();
         let mut text_output = None;
+        self.last_text_tokens.clear();
         
         // Encode input with mimi
         let codes = self.mimi.encode_step(&in_pcm.into())?;
@@ -146,11 +260,11 @@ impl StreamingModel {
                 // Extract text if valid
                 if text_token != 0 && text_token != 3 {
                     if let Some(text) = self.decode_text(text_token) {
-                        if text_output.is_none() {
-                            text_output = Some(text);
-                        } else {
-                            text_output.as_mut().unwrap().push_str(&text);
+                        match text_output.as_mut() {
+                            None => text_output = Some(text.clone()),
+                            Some(t) => t.push_str(&text),
                         }
+                        self.last_text_tokens.push((text_token, text));
                     }
                 }
                 self.prev_text_token = text_token;
@@ -173,8 +287,23 @@ impl StreamingModel {
             }
         }
         
+        if self.pad_output {
+            if out_pcm.is_empty() {
+                out_pcm = vec![0.; FRAME_SIZE];
+            } else {
+                self.pad_output = false;
+            }
+        }
+        if let Some(end) = self.segmenter.observe(pcm, &out_pcm, text_output.is_some()) {
+            self.next_segment(end);
+        }
+        
         let elapsed = start.elapsed().as_secs_f32();
         self.frame_times.push(elapsed);
+        self.frames_processed += 1;
+        if self.frame_times.len() >= 2 * FRAME_TIMES_WINDOW {
+            self.frame_times.drain(..FRAME_TIMES_WINDOW);
+        }
         
         Ok((out_pcm, text_output))
     }
@@ -197,8 +326,81 @@ impl StreamingModel {
         }
     }
     
+    /// Processing time stats over the recent frames, with the number of frames
+    /// since the start of the session.
     pub fn get_stats(&self) -> ModelStats {
-        if self.frame_times.is_empty() {
+        let mut stats = ModelStats::from_frame_times(&self.frame_times);
+        stats.frames_processed = self.frames_processed;
+        stats
+    }
+    
+    pub fn info(&self) -> ModelInfo {
+        self.info.clone()
+    }
+    
+    /// Text tokens decoded during the last frame, with their pieces.
+    pub fn last_text_tokens(&self) -> &[(u32, String)] {
+        &self.last_text_tokens
+    }
+}
+
+fn new_state(
+    lm_model: moshi::lm::LmModel,
+    config: &moshi::lm_generate_multistream::Config,
+    sampling: &SamplingConfig,
+    segment_idx: u64,
+    segmenter: &Segmenter,
+    cfg_alpha: Option<f64>,
+) -> moshi::lm_generate_multistream::State {
+    // Vary the seeds between segments so that they do not repeat the same draws.
+    let mut sampling = sampling.clone();
+    sampling.text.seed = sampling.text.seed.wrapping_add(segment_idx);
+    sampling.audio.seed = sampling.audio.seed.wrapping_add(segment_idx);
+    moshi::lm_generate_multistream::State::new(
+        lm_model,
+        // A frame is usually a single step, leave some room for more.
+        segmenter.max_steps() + 16,
+        sampling.audio.logits_processor(),
+        sampling.text.logits_processor(),
+        None,
+        None,
+        cfg_alpha,
+        config.clone(),
+    )
+}
+
+impl FrameProcessor for StreamingModel {
+    fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)> {
+        StreamingModel::process_frame(self, pcm)
+    }
+
+    fn stats(&self) -> ModelStats {
+        self.get_stats()
+    }
+
+    fn reset(&mut self) {
+        StreamingModel::reset(self)
+    }
+
+    fn info(&self) -> ModelInfo {
+        StreamingModel::info(self)
+    }
+
+    fn last_text_tokens(&self) -> &[(u32, String)] {
+        StreamingModel::last_text_tokens(self)
+    }
+}
+
+pub struct ModelStats {
+    pub avg_time_ms: f32,
+    pub p95_time_ms: f32,
+    pub frames_processed: usize,
+}
+
+impl ModelStats {
+    /// Summarizes per-frame processing times, given in seconds.
+    pub fn from_frame_times(frame_times: &[f32]) -> Self {
+        if frame_times.is_empty() {
             return ModelStats {
                 avg_time_ms: 0.0,
                 p95_time_ms: 0.0,
@@ -206,7 +408,7 @@ impl StreamingModel {
             };
         }
         
-        let mut sorted = self.frame_times.clone();
+        let mut sorted = frame_times.to_vec();
         sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
         
         let avg = sorted.iter().sum::<f32>() / sorted.len() as f32;
@@ -221,45 +423,138 @@ impl StreamingModel {
     }
 }
 
-pub struct ModelStats {
-    pub avg_time_ms: f32,
-    pub p95_time_ms: f32,
-    pub frames_processed: usize,
+/// Text generated for one frame, located in the generated audio.
+#[derive(Debug, Clone)]
+pub struct TimedText {
+    /// Number of audio samples generated up to and including this frame.
+    pub sample: usize,
+    pub text: String,
+}
+
+/// What the model thread sends to the text printer.
+#[derive(Debug, Clone)]
+pub enum TextUpdate {
+    Text(TimedText),
+    /// A long silence ends the current subtitle cue and line of transcript.
+    Cut,
+}
+
+/// Where the model thread sends what it generates.
+pub struct ModelOutputs {
+    pub audio_tx: mpsc::SyncSender<Vec<f32>>,
+    pub text_tx: mpsc::Sender<TextUpdate>,
+    pub events_tx: Option<mpsc::Sender<Event>>,
+    /// Every input frame along with the audio generated for it, empty audio
+    /// included so that the steps stay aligned.
+    pub steps_tx: Option<mpsc::Sender<([f32; FRAME_SIZE], Vec<f32>)>>,
 }
 
 /// Run model inference thread
-pub fn run_model_thread(
-    mut model: StreamingModel,
+///
+/// With `vad`, the utterances of the input are reported in the events and the
+/// model is not run during long silences, silence being generated instead.
+pub fn run_model_thread<P: FrameProcessor>(
+    mut model: P,
     input_rx: mpsc::Receiver<[f32; FRAME_SIZE]>,
-    audio_tx: mpsc::SyncSender<Vec<f32>>,
-    text_tx: mpsc::Sender<String>,
+    outputs: ModelOutputs,
+    vad: Option<VadConfig>,
+    backpressure: bool,
     shutdown: std::sync::Arc<std::sync::atomic::AtomicBool>,
-) -> Result<ModelStats> {
+) -> Result<(ModelStats, usize)> {
     use std::sync::atomic::Ordering;
     
+    let ModelOutputs { audio_tx, text_tx, events_tx, steps_tx } = outputs;
     tracing::info!("Model thread started");
     let mut frames_received = 0u64;
+    let mut samples_generated = 0usize;
     let mut last_log = std::time::Instant::now();
+    let mut vad = vad.map(|config| (Vad::new(&config), config.on_long_silence));
+    let mut output_active = false;
+    
+    let send_event = |event: Event| {
+        if let Some(events_tx) = events_tx.as_ref() {
+            let _ = events_tx.send(event);
+        }
+    };
     
     while !shutdown.load(Ordering::Relaxed) {
         match input_rx.recv_timeout(std::time::Duration::from_millis(100)) {
             Ok(frame) => {
                 frames_received += 1;
                 
-                // Calculate RMS of input frame to detect silence
-                let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
-                
                 // Log every 5 seconds to confirm model is receiving input
                 if last_log.elapsed().as_secs() >= 5 {
-                    tracing::info!("🎤 Model received {} frames so far (latest RMS: {:.4})", frames_received, rms);
+                    let input_rms = rms(&frame);
+                    tracing::info!("🎤 Model received {} frames so far (latest RMS: {:.4})", frames_received, input_rms);
                     last_log = std::time::Instant::now();
                 }
                 
-                match model.process_frame(&frame) {
+                let mut idle = false;
+                if let Some((vad, action)) = vad.as_mut() {
+                    match vad.push(&frame, output_active) {
+                        Some(VadEvent::UtteranceStart { step }) => {
+                            tracing::info!("Utterance started at step {}", step);
+                            send_event(Event::UtteranceStart { step });
+                        }
+                        Some(VadEvent::UtteranceEnd { step }) => {
+                            tracing::info!("Utterance ended at step {}", step);
+                            send_event(Event::UtteranceEnd { step });
+                        }
+                        Some(VadEvent::LongSilence { step }) => {
+                            tracing::info!(
+                                "Long silence at step {}, pausing the model ({})",
+                                step,
+                                action
+                            );
+                            send_event(Event::LongSilence { step, action: action.to_string() });
+                            if *action != SilenceAction::None {
+                                let _ = text_tx.send(TextUpdate::Cut);
+                            }
+                            if *action == SilenceAction::Reset {
+                                model.reset();
+                            }
+                        }
+                        None => {}
+                    }
+                    idle = vad.is_idle();
+                }
+                
+                let frame_start = Instant::now();
+                // The playback and the files keep going with silence while the
+                // model is idle.
+                let result = if idle {
+                    Ok((vec![0.; FRAME_SIZE], None))
+                } else {
+                    model.process_frame(&frame)
+                };
+                match result {
                     Ok((audio, text)) => {
+                        output_active = text.is_some() || rms(&audio) >= QUIET_RMS;
+                        if let Some(events_tx) = events_tx.as_ref() {
+                            let output = FrameOutput {
+                                step: frames_received as usize - 1,
+                                input: &frame,
+                                offset: samples_generated,
+                                pcm: &audio,
+                                text: text.as_deref(),
+                                text_tokens: if idle { &[] } else { model.last_text_tokens() },
+                                processing_time: frame_start.elapsed(),
+                            };
+                            for event in output.events() {
+                                let _ = events_tx.send(event);
+                            }
+                        }
+                        samples_generated += audio.len();
+                        if let Some(steps_tx) = steps_tx.as_ref() {
+                            let _ = steps_tx.send((frame, audio.clone()));
+                        }
                         if !audio.is_empty() {
                             tracing::info!("🔊 Model generated {} audio samples", audio.len());
-                            if let Err(e) = audio_tx.try_send(audio) {
+                            if backpressure {
+                                if audio_tx.send(audio).is_err() {
+                                    tracing::error!("❌ Audio receiver dropped");
+                                }
+                            } else if let Err(e) = audio_tx.try_send(audio) {
                                 tracing::error!("❌ Failed to send audio to buffer: {:?}", e);
                             }
                         } else {
@@ -267,7 +562,8 @@ pub fn run_model_thread(
                         }
                         if let Some(text) = text {
                             tracing::info!("📝 Text: {}", text);
-                            let _ = text_tx.send(text);
+                            let timed = TimedText { sample: samples_generated, text };
+                            let _ = text_tx.send(TextUpdate::Text(timed));
                         }
                     }
                     Err(e) => {
@@ -286,7 +582,7 @@ pub fn run_model_thread(
         }
     }
     
-    let stats = model.get_stats();
+    let stats = model.stats();
     tracing::info!("Model thread finished");
-    Ok(stats)
+    Ok((stats, samples_generated))
 }
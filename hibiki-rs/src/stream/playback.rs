@@ -4,11 +4,15 @@
 
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, StreamTrait};
-use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
-use super::resampler::TARGET_SAMPLE_RATE;
+use super::devices::choose_output_config;
+use super::recovery::{StreamProbe, Watchdog};
+use super::resampler::{OutputResampler, TARGET_SAMPLE_RATE};
 
+// The buffer size and thresholds are counted at 24kHz and scaled to the device
+// rate.
 const RING_BUFFER_SIZE: usize = TARGET_SAMPLE_RATE * 12; // 12 seconds - needed for slower 2B model
 const PAUSE_THRESHOLD: usize = TARGET_SAMPLE_RATE / 100; // 0.1s = 2400 samples - pause when buffer critically low
 const RESUME_THRESHOLD: usize = TARGET_SAMPLE_RATE / 10; // 0.25s = 6000 samples - resume when buffer refilled (MUST BE > PAUSE!)
@@ -21,19 +25,23 @@ struct PlaybackBuffer {
 }
 
 impl PlaybackBuffer {
-    fn new() -> Self {
+    fn new(size: usize) -> Self {
         Self {
-            buffer: vec![0.0; RING_BUFFER_SIZE],
+            buffer: vec![0.0; size],
             write_pos: 0,
             read_pos: 0,
         }
     }
     
+    fn size(&self) -> usize {
+        self.buffer.len()
+    }
+    
     fn available(&self) -> usize {
         if self.write_pos >= self.read_pos {
             self.write_pos - self.read_pos
         } else {
-            RING_BUFFER_SIZE - self.read_pos + self.write_pos
+            self.size() - self.read_pos + self.write_pos
         }
     }
     
@@ -47,11 +55,11 @@ impl PlaybackBuffer {
         }
         
         // Handle wrap-around
-        if self.read_pos + to_read <= RING_BUFFER_SIZE {
+        if self.read_pos + to_read <= self.size() {
             out.extend_from_slice(&self.buffer[self.read_pos..self.read_pos + to_read]);
-            self.read_pos = (self.read_pos + to_read) % RING_BUFFER_SIZE;
+            self.read_pos = (self.read_pos + to_read) % self.size();
         } else {
-            let first_chunk = RING_BUFFER_SIZE - self.read_pos;
+            let first_chunk = self.size() - self.read_pos;
             out.extend_from_slice(&self.buffer[self.read_pos..]);
             out.extend_from_slice(&self.buffer[..to_read - first_chunk]);
             self.read_pos = to_read - first_chunk;
@@ -60,23 +68,23 @@ impl PlaybackBuffer {
     
     fn write(&mut self, samples: &[f32]) -> bool {
         let available = self.available();
-        let free = RING_BUFFER_SIZE - available - 1; // -1 to distinguish full from empty
+        let free = self.size() - available - 1; // -1 to distinguish full from empty
         let overflowed = samples.len() > free;
         
         if overflowed {
             // Drop oldest samples by advancing read pointer
             let to_drop = samples.len() - free;
-            self.read_pos = (self.read_pos + to_drop) % RING_BUFFER_SIZE;
+            self.read_pos = (self.read_pos + to_drop) % self.size();
         }
         
         let to_write = samples.len().min(free);
         
         // Handle wrap-around
-        if self.write_pos + to_write <= RING_BUFFER_SIZE {
+        if self.write_pos + to_write <= self.size() {
             self.buffer[self.write_pos..self.write_pos + to_write].copy_from_slice(&samples[..to_write]);
-            self.write_pos = (self.write_pos + to_write) % RING_BUFFER_SIZE;
+            self.write_pos = (self.write_pos + to_write) % self.size();
         } else {
-            let first_chunk = RING_BUFFER_SIZE - self.write_pos;
+            let first_chunk = self.size() - self.write_pos;
             self.buffer[self.write_pos..].copy_from_slice(&samples[..first_chunk]);
             self.buffer[..to_write - first_chunk].copy_from_slice(&samples[first_chunk..to_write]);
             self.write_pos = to_write - first_chunk;
@@ -89,32 +97,58 @@ impl PlaybackBuffer {
 pub struct SpeakerSink {
     buffer: Arc<Mutex<PlaybackBuffer>>,
     _stream: cpal::Stream,
-    playing: Arc<AtomicBool>,
-    started: Arc<AtomicBool>, // Track if we've ever started (for initial fill)
+    // Converts the 24kHz audio to the device rate, if it differs
+    resampler: Option<OutputResampler>,
+    sample_rate: usize,
     underrun_count: Arc<AtomicU64>,
     overflow_count: Arc<AtomicU64>,
+    watchdog: Watchdog,
 }
 
 impl SpeakerSink {
     pub fn new(device: cpal::Device) -> Result<Self> {
-        // CRITICAL: Force 24kHz output to avoid resampling artifacts!
-        let config = cpal::StreamConfig {
-            channels: 1,
-            sample_rate: cpal::SampleRate(TARGET_SAMPLE_RATE as u32),
-            buffer_size: cpal::BufferSize::Default,
+        Self::open(device, Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)))
+    }
+    
+    /// Opens another device in place of this one once it died, the buffered
+    /// audio is lost but the underrun and overflow counts carry over
+    pub fn reopen(&self, device: cpal::Device) -> Result<Self> {
+        Self::open(device, self.underrun_count.clone(), self.overflow_count.clone())
+    }
+    
+    fn open(
+        device: cpal::Device,
+        underrun_count: Arc<AtomicU64>,
+        overflow_count: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        // Many devices reject 24kHz mono, use a config they support and convert
+        let supported: Vec<_> = device.supported_output_configs()?.collect();
+        let config = choose_output_config(&supported, device.default_output_config().ok())
+            .context("The output device has no supported config")?;
+        
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as usize;
+        let resampler = if sample_rate == TARGET_SAMPLE_RATE {
+            None
+        } else {
+            Some(OutputResampler::new(sample_rate)?)
         };
         
         tracing::info!(
-            "Speaker output config: {} channels, {} Hz (forced, no resampling)",
-            config.channels,
-            config.sample_rate.0,
+            "Speaker output config: {} channels, {} Hz, {:?} ({})",
+            channels,
+            sample_rate,
+            config.sample_format(),
+            if resampler.is_some() { "resampled from 24 kHz" } else { "no resampling" },
         );
         
-        let sample_rate = config.sample_rate.0 as usize;
-        let channels = config.channels as usize;
+        // Thresholds at the device rate
+        let scale = |samples: usize| samples * sample_rate / TARGET_SAMPLE_RATE;
+        let (pause_threshold, resume_threshold) = (scale(PAUSE_THRESHOLD), scale(RESUME_THRESHOLD));
+        let initial_fill_threshold = scale(INITIAL_FILL_THRESHOLD);
         
         // Optimized playback buffer with read cursor
-        let buffer = Arc::new(Mutex::new(PlaybackBuffer::new()));
+        let buffer = Arc::new(Mutex::new(PlaybackBuffer::new(scale(RING_BUFFER_SIZE))));
         let buffer_cb = buffer.clone();
         
         let playing = Arc::new(AtomicBool::new(false)); // Start paused until buffer fills
@@ -123,98 +157,110 @@ impl SpeakerSink {
         let started = Arc::new(AtomicBool::new(false)); // Track initial fill
         let started_cb = started.clone();
         
-        let underrun_count = Arc::new(AtomicU64::new(0));
         let underrun_count_cb = underrun_count.clone();
         
-        let overflow_count = Arc::new(AtomicU64::new(0));
-        
-        // No resampler needed - we force 24kHz output!
+        // Notices when the device goes away
+        let watchdog = Watchdog::new();
+        let probe = watchdog.probe();
+        let probe_cb = watchdog.probe();
         
-        let stream = device.build_output_stream(
-                    &config,
-                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                        thread_local! {
-                            static TEMP_BUF: std::cell::RefCell<Vec<f32>> = std::cell::RefCell::new(Vec::with_capacity(4096));
-                            static LAST_LOG: std::cell::Cell<std::time::Instant> = std::cell::Cell::new(std::time::Instant::now());
-                        }
-                        
-                        let frames = data.len() / channels;
-                        
-                        // Check buffer level first WITHOUT draining
-                        let buffer_len = buffer_cb.lock().unwrap().available();
-                        let is_playing = playing_cb.load(Ordering::Relaxed);
-                        let has_started = started_cb.load(Ordering::Relaxed);
-                        
-                        // Smarter hysteresis with initial fill requirement
-                        if !has_started {
-                            if buffer_len >= INITIAL_FILL_THRESHOLD {
-                                started_cb.store(true, Ordering::Relaxed);
-                                playing_cb.store(true, Ordering::Relaxed);
-                                tracing::info!("🎵 Playback STARTED: initial buffer = {} samples ({:.2}s)", 
-                                    buffer_len, buffer_len as f32 / TARGET_SAMPLE_RATE as f32);
-                            } else {
-                                data.fill(0.0);
-                                LAST_LOG.with(|last| {
-                                    if last.get().elapsed().as_millis() > 500 {
-                                        tracing::info!("⏳ Buffering... {}/{} samples ({:.1}%)", 
-                                            buffer_len, INITIAL_FILL_THRESHOLD,
-                                            100.0 * buffer_len as f32 / INITIAL_FILL_THRESHOLD as f32);
-                                        last.set(std::time::Instant::now());
-                                    }
-                                });
-                                return;  // Don't drain buffer yet!
-                            }
-                        }
-                        
-                        // After initial start: use tighter hysteresis
-                        if !is_playing && buffer_len >= RESUME_THRESHOLD {
-                            playing_cb.store(true, Ordering::Relaxed);
-                            tracing::warn!("▶️  RESUMED: buffer refilled to {} samples ({:.2}s)", 
-                                buffer_len, buffer_len as f32 / TARGET_SAMPLE_RATE as f32);
-                        } else if is_playing && buffer_len < PAUSE_THRESHOLD {
-                            playing_cb.store(false, Ordering::Relaxed);
-                            tracing::error!("⏸️  PAUSED: buffer depleted to {} samples ({:.2}s) - UNDERRUN!", 
-                                buffer_len, buffer_len as f32 / TARGET_SAMPLE_RATE as f32);
-                            underrun_count_cb.fetch_add(1, Ordering::Relaxed);
+        let fill = move |data: &mut [f32]| {
+            thread_local! {
+                static TEMP_BUF: std::cell::RefCell<Vec<f32>> = std::cell::RefCell::new(Vec::with_capacity(4096));
+                static LAST_LOG: std::cell::Cell<std::time::Instant> = std::cell::Cell::new(std::time::Instant::now());
+            }
+            
+            probe_cb.tick();
+            let frames = data.len() / channels;
+            
+            // Check buffer level first WITHOUT draining
+            let buffer_len = buffer_cb.lock().unwrap().available();
+            let is_playing = playing_cb.load(Ordering::Relaxed);
+            let has_started = started_cb.load(Ordering::Relaxed);
+            
+            // Smarter hysteresis with initial fill requirement
+            if !has_started {
+                if buffer_len >= initial_fill_threshold {
+                    started_cb.store(true, Ordering::Relaxed);
+                    playing_cb.store(true, Ordering::Relaxed);
+                    tracing::info!("🎵 Playback STARTED: initial buffer = {} samples ({:.2}s)", 
+                        buffer_len, buffer_len as f32 / sample_rate as f32);
+                } else {
+                    data.fill(0.0);
+                    LAST_LOG.with(|last| {
+                        if last.get().elapsed().as_millis() > 500 {
+                            tracing::info!("⏳ Buffering... {}/{} samples ({:.1}%)", 
+                                buffer_len, initial_fill_threshold,
+                                100.0 * buffer_len as f32 / initial_fill_threshold as f32);
+                            last.set(std::time::Instant::now());
                         }
-                        
-                        // NOW read from buffer (only if playing)
-                        let to_read = if playing_cb.load(Ordering::Relaxed) {
-                            let mut buf = buffer_cb.lock().unwrap();
-                            TEMP_BUF.with(|temp| {
-                                let mut temp = temp.borrow_mut();
-                                buf.read(frames, &mut temp);
-                                temp.len()
-                            })
-                        } else {
-                            0
-                        };
-                        
-                        // Write samples WITHOUT holding any lock
-                        if to_read > 0 {
-                            TEMP_BUF.with(|temp| {
-                                let temp = temp.borrow();
-                                
-                                for i in 0..to_read {
-                                    let sample = temp[i];
-                                    for ch in 0..channels {
-                                        data[i * channels + ch] = sample;
-                                    }
-                                }
-                                // Fill remainder with silence if needed
-                                for i in (to_read * channels)..(data.len()) {
-                                    data[i] = 0.0;
-                                }
-                            });
-                        } else {
-                            data.fill(0.0);
+                    });
+                    return;  // Don't drain buffer yet!
+                }
+            }
+            
+            // After initial start: use tighter hysteresis
+            if !is_playing && buffer_len >= resume_threshold {
+                playing_cb.store(true, Ordering::Relaxed);
+                tracing::warn!("▶️  RESUMED: buffer refilled to {} samples ({:.2}s)", 
+                    buffer_len, buffer_len as f32 / sample_rate as f32);
+            } else if is_playing && buffer_len < pause_threshold {
+                playing_cb.store(false, Ordering::Relaxed);
+                tracing::error!("⏸️  PAUSED: buffer depleted to {} samples ({:.2}s) - UNDERRUN!", 
+                    buffer_len, buffer_len as f32 / sample_rate as f32);
+                underrun_count_cb.fetch_add(1, Ordering::Relaxed);
+            }
+            
+            // NOW read from buffer (only if playing)
+            let to_read = if playing_cb.load(Ordering::Relaxed) {
+                let mut buf = buffer_cb.lock().unwrap();
+                TEMP_BUF.with(|temp| {
+                    let mut temp = temp.borrow_mut();
+                    buf.read(frames, &mut temp);
+                    temp.len()
+                })
+            } else {
+                0
+            };
+            
+            // Write samples WITHOUT holding any lock, upmixed to every channel
+            if to_read > 0 {
+                TEMP_BUF.with(|temp| {
+                    let temp = temp.borrow();
+                    
+                    for i in 0..to_read {
+                        let sample = temp[i];
+                        for ch in 0..channels {
+                            data[i * channels + ch] = sample;
                         }
-                    },
-                    move |err| {
-                        tracing::error!("Speaker output stream error: {}", err);
-                    },
-                    None,
-                )?;
+                    }
+                    // Fill remainder with silence if needed
+                    data[to_read * channels..].fill(0.0);
+                });
+            } else {
+                data.fill(0.0);
+            }
+        };
+        let sample_format = config.sample_format();
+        let config: cpal::StreamConfig = config.into();
+        macro_rules! build {
+            ($sample:ty) => {
+                build_output_stream::<$sample>(&device, &config, fill, probe)?
+            };
+        }
+        let stream = match sample_format {
+            cpal::SampleFormat::I8 => build!(i8),
+            cpal::SampleFormat::I16 => build!(i16),
+            cpal::SampleFormat::I32 => build!(i32),
+            cpal::SampleFormat::I64 => build!(i64),
+            cpal::SampleFormat::U8 => build!(u8),
+            cpal::SampleFormat::U16 => build!(u16),
+            cpal::SampleFormat::U32 => build!(u32),
+            cpal::SampleFormat::U64 => build!(u64),
+            cpal::SampleFormat::F32 => build!(f32),
+            cpal::SampleFormat::F64 => build!(f64),
+            _ => anyhow::bail!("Unsupported sample format: {:?}", sample_format),
+        };
         
         stream.play()?;
         tracing::info!("Speaker playback started");
@@ -222,16 +268,41 @@ impl SpeakerSink {
         Ok(Self {
             buffer,
             _stream: stream,
-            playing,
-            started,
+            resampler,
+            sample_rate,
             underrun_count,
             overflow_count,
+            watchdog,
         })
     }
     
-    /// Push samples to playback (non-blocking)
+    /// True once the device went away or stopped asking for audio
+    pub fn is_dead(&mut self) -> bool {
+        self.watchdog.is_dead()
+    }
+    
+    /// Push 24kHz samples to playback (non-blocking)
     pub fn push_samples(&mut self, samples: &[f32]) -> Result<()> {
-        // No resampling needed - direct write at 24kHz
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let resampled = resampler.push_samples(samples)?;
+                self.write_buffer(&resampled);
+            }
+            None => self.write_buffer(samples),
+        }
+        Ok(())
+    }
+    
+    /// Pushes the audio held back by the resampler, once the input has ended
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(mut resampler) = self.resampler.take() {
+            let rest = resampler.finish()?;
+            self.write_buffer(&rest);
+        }
+        Ok(())
+    }
+    
+    fn write_buffer(&mut self, samples: &[f32]) {
         let mut buf = self.buffer.lock().unwrap();
         let before = buf.available();
         if buf.write(samples) {
@@ -240,11 +311,11 @@ impl SpeakerSink {
         }
         let after = buf.available();
         tracing::debug!("📥 Pushed {} samples to buffer (level: {} → {})", samples.len(), before, after);
-        Ok(())
     }
     
+    /// Samples waiting to be played, counted at 24kHz
     pub fn buffer_level(&self) -> usize {
-        self.buffer.lock().unwrap().available()
+        self.buffer.lock().unwrap().available() * TARGET_SAMPLE_RATE / self.sample_rate
     }
     
     pub fn underrun_count(&self) -> u64 {
@@ -255,3 +326,30 @@ impl SpeakerSink {
         self.overflow_count.load(Ordering::Relaxed)
     }
 }
+
+/// Builds the speaker stream for samples of type `T`, `fill` writing f32
+/// samples in [-1, 1] to a buffer reused across callbacks
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut fill: impl FnMut(&mut [f32]) + Send + 'static,
+    probe: StreamProbe,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let mut float_data: Vec<f32> = Vec::new();
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            float_data.resize(data.len(), 0.0);
+            fill(&mut float_data);
+            for (out, &sample) in data.iter_mut().zip(float_data.iter()) {
+                *out = T::from_sample(sample);
+            }
+        },
+        move |err| probe.error("Speaker output", err),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,158 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::events::ModelInfo;
+
+use super::model::ModelStats;
+use super::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+
+/// Emit a text piece every 2s (25 frames) from the mock processors.
+const MOCK_TEXT_INTERVAL: usize = 25;
+
+/// Something that turns 80ms input frames into generated audio and text.
+///
+/// `StreamingModel` is the real implementation; the mock processors below let
+/// the streaming pipeline run without model weights.
+pub trait FrameProcessor {
+    /// Process one 80ms frame (1920 samples) and return generated audio + text
+    fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)>;
+
+    fn stats(&self) -> ModelStats;
+
+    /// Forgets everything about the frames processed so far, so that the next
+    /// frame starts an independent session.
+    fn reset(&mut self);
+
+    /// Describes the processor for the session start event.
+    fn info(&self) -> ModelInfo {
+        ModelInfo::default()
+    }
+
+    /// Token ids and pieces of the text returned by the last `process_frame`.
+    fn last_text_tokens(&self) -> &[(u32, String)] {
+        &[]
+    }
+}
+
+/// Returns its input delayed by a fixed number of frames.
+///
+/// Like the real model, no audio is produced while the delay line fills up.
+pub struct EchoProcessor {
+    delay_frames: usize,
+    queue: VecDeque<[f32; FRAME_SIZE]>,
+    frame_idx: usize,
+    frame_times: Vec<f32>,
+}
+
+impl EchoProcessor {
+    pub fn new(delay_frames: usize) -> Self {
+        Self {
+            delay_frames,
+            queue: VecDeque::with_capacity(delay_frames + 1),
+            frame_idx: 0,
+            frame_times: Vec::new(),
+        }
+    }
+}
+
+impl FrameProcessor for EchoProcessor {
+    fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)> {
+        let start = Instant::now();
+        self.queue.push_back(*pcm);
+        let out_pcm = if self.queue.len() > self.delay_frames {
+            self.queue.pop_front().map_or_else(Vec::new, |f| f.to_vec())
+        } else {
+            Vec::new()
+        };
+        let text = mock_text(self.frame_idx);
+        self.frame_idx += 1;
+        self.frame_times.push(start.elapsed().as_secs_f32());
+        Ok((out_pcm, text))
+    }
+
+    fn stats(&self) -> ModelStats {
+        ModelStats::from_frame_times(&self.frame_times)
+    }
+
+    fn reset(&mut self) {
+        self.queue.clear();
+        self.frame_idx = 0;
+        self.frame_times.clear();
+    }
+
+    fn info(&self) -> ModelInfo {
+        ModelInfo::named("echo")
+    }
+}
+
+/// Ignores its input and generates a continuous sine tone after a fixed delay.
+pub struct ToneProcessor {
+    delay_frames: usize,
+    frequency: f32,
+    amplitude: f32,
+    phase: f32,
+    frame_idx: usize,
+    frame_times: Vec<f32>,
+}
+
+impl ToneProcessor {
+    pub fn new(delay_frames: usize, frequency: f32) -> Self {
+        Self {
+            delay_frames,
+            frequency,
+            amplitude: 0.25,
+            phase: 0.0,
+            frame_idx: 0,
+            frame_times: Vec::new(),
+        }
+    }
+}
+
+impl FrameProcessor for ToneProcessor {
+    fn process_frame(&mut self, _pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)> {
+        let start = Instant::now();
+        let out_pcm = if self.frame_idx >= self.delay_frames {
+            let step = 2.0 * std::f32::consts::PI * self.frequency / TARGET_SAMPLE_RATE as f32;
+            let mut out = Vec::with_capacity(FRAME_SIZE);
+            for _ in 0..FRAME_SIZE {
+                out.push(self.amplitude * self.phase.sin());
+                self.phase = (self.phase + step) % (2.0 * std::f32::consts::PI);
+            }
+            out
+        } else {
+            Vec::new()
+        };
+        let text = mock_text(self.frame_idx);
+        self.frame_idx += 1;
+        self.frame_times.push(start.elapsed().as_secs_f32());
+        Ok((out_pcm, text))
+    }
+
+    fn stats(&self) -> ModelStats {
+        ModelStats::from_frame_times(&self.frame_times)
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+        self.frame_idx = 0;
+        self.frame_times.clear();
+    }
+
+    fn info(&self) -> ModelInfo {
+        ModelInfo::named("tone")
+    }
+}
+
+fn mock_text(frame_idx: usize) -> Option<String> {
+    if frame_idx.is_multiple_of(MOCK_TEXT_INTERVAL) {
+        Some(format!(" [{frame_idx}]"))
+    } else {
+        None
+    }
+}
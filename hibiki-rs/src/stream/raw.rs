@@ -0,0 +1,231 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Headerless PCM on stdin and stdout, to use the streaming mode in shell
+//! pipelines such as `arecord | hibiki stream --input-file - ... | aplay`.
+
+use anyhow::{bail, Result};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use super::input::AudioFrame;
+use super::resampler::{InputChannel, StreamingResampler, TARGET_SAMPLE_RATE};
+
+/// Returns true if `path` designates stdin rather than a file.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Encoding of the raw samples, interleaved when there are several channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawSampleFormat {
+    #[default]
+    S16Le,
+    F32Le,
+}
+
+impl RawSampleFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            Self::S16Le => 2,
+            Self::F32Le => 4,
+        }
+    }
+
+    /// Appends the samples held in `bytes`, which must be a whole number of
+    /// samples.
+    pub fn decode(&self, bytes: &[u8], out: &mut Vec<f32>) {
+        match self {
+            Self::S16Le => out.extend(
+                bytes.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0),
+            ),
+            Self::F32Le => out.extend(
+                bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ),
+        }
+    }
+
+    pub fn encode(&self, pcm: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Self::S16Le => {
+                for &sample in pcm {
+                    let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            Self::F32Le => {
+                for &sample in pcm {
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for RawSampleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "s16le" => Ok(Self::S16Le),
+            "f32le" => Ok(Self::F32Le),
+            _ => bail!("unknown sample format {s}, expected s16le or f32le"),
+        }
+    }
+}
+
+impl std::fmt::Display for RawSampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::S16Le => write!(f, "s16le"),
+            Self::F32Le => write!(f, "f32le"),
+        }
+    }
+}
+
+/// Layout of the PCM read from stdin, which has no header to describe it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawInputFormat {
+    pub sample_format: RawSampleFormat,
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl Default for RawInputFormat {
+    fn default() -> Self {
+        Self {
+            sample_format: RawSampleFormat::S16Le,
+            sample_rate: TARGET_SAMPLE_RATE as u32,
+            channels: 1,
+        }
+    }
+}
+
+impl RawInputFormat {
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate == 0 {
+            bail!("the raw input sample rate must be positive")
+        }
+        if self.channels == 0 {
+            bail!("the raw input must have at least one channel")
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RawInputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} Hz {}ch", self.sample_format, self.sample_rate, self.channels)
+    }
+}
+
+/// Reads raw PCM until the end of the stream and emits 80ms frames at 24kHz.
+///
+/// As for files, the channels are mixed or selected by `channel`, half a
+/// second of silence is appended at the end and the frames are paced to wall
+/// clock unless `pace` is false.
+pub fn run_raw_input<R: Read>(
+    mut reader: R,
+    format: &RawInputFormat,
+    channel: InputChannel,
+    pace: bool,
+    tx: mpsc::SyncSender<AudioFrame>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    format.validate()?;
+    let sample_bytes = format.sample_format.bytes_per_sample() * format.channels;
+    let mut resampler =
+        StreamingResampler::new(format.sample_rate as usize, format.channels, channel)?;
+    // Reads hold up to 20ms of audio, the bytes of an incomplete sample are
+    // kept at the start of the buffer for the next read.
+    let mut buffer = vec![0u8; sample_bytes * (format.sample_rate as usize / 50).max(1)];
+    let mut buffered = 0;
+    let mut samples = Vec::new();
+
+    let frame_duration = Duration::from_millis(80);
+    let start_time = Instant::now();
+    let mut frame_idx = 0;
+    let mut send = |frames: Vec<AudioFrame>| -> bool {
+        for frame in frames {
+            if pace {
+                let expected_time = start_time + frame_duration * frame_idx;
+                let now = Instant::now();
+                if now < expected_time {
+                    std::thread::sleep(expected_time - now);
+                }
+            }
+            if tx.send(frame).is_err() {
+                tracing::info!("Raw input: receiver dropped");
+                return false;
+            }
+            frame_idx += 1;
+        }
+        true
+    };
+
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            tracing::info!("Raw input shutdown requested");
+            return Ok(());
+        }
+        let read = match reader.read(&mut buffer[buffered..]) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        buffered += read;
+        let complete = buffered - buffered % sample_bytes;
+        samples.clear();
+        format.sample_format.decode(&buffer[..complete], &mut samples);
+        buffer.copy_within(complete..buffered, 0);
+        buffered -= complete;
+        if !send(resampler.push_samples(&samples)?) {
+            return Ok(());
+        }
+    }
+    if buffered > 0 {
+        tracing::warn!("Raw input: ignoring {} trailing bytes", buffered);
+    }
+
+    // Pad with silence at end
+    let silence = vec![0.0; format.sample_rate as usize / 2 * format.channels];
+    let mut frames = resampler.push_samples(&silence)?;
+    frames.extend(resampler.flush()?);
+    if send(frames) {
+        tracing::info!(
+            "Raw input complete: {} frames in {:.1}s",
+            frame_idx,
+            start_time.elapsed().as_secs_f32()
+        );
+    }
+    Ok(())
+}
+
+/// Writes the generated 24kHz mono audio as raw PCM, flushing after every
+/// chunk so that the next program in the pipeline plays it right away.
+pub fn run_raw_output<W: Write>(
+    mut out: W,
+    format: RawSampleFormat,
+    rx: mpsc::Receiver<Vec<f32>>,
+) -> Result<()> {
+    let mut bytes = Vec::new();
+    let mut total_samples = 0;
+    while let Ok(samples) = rx.recv() {
+        bytes.clear();
+        format.encode(&samples, &mut bytes);
+        out.write_all(&bytes)?;
+        out.flush()?;
+        total_samples += samples.len();
+    }
+    tracing::info!(
+        "Raw output complete: {} samples, {:.2}s",
+        total_samples,
+        total_samples as f32 / TARGET_SAMPLE_RATE as f32
+    );
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Detection of dead audio streams, e.g. an unplugged USB headset, and the
+//! retries to reopen their device.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A stream whose callbacks stopped for this long is considered dead.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+/// Delays before each attempt to reopen a device, about 15s in total.
+pub const BACKOFF: [Duration; 5] = [
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(4),
+    Duration::from_secs(8),
+];
+/// How often the retries check for shutdown and call `idle`.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handle given to the callbacks of a stream, to report that it is alive.
+///
+/// The callbacks must tick it every time, including when the audio they carry
+/// gets dropped, so that a slow consumer is not mistaken for a dead device.
+#[derive(Clone)]
+pub struct StreamProbe {
+    callbacks: Arc<AtomicU64>,
+    failed: Arc<AtomicBool>,
+}
+
+impl StreamProbe {
+    /// To call from the data callback.
+    pub fn tick(&self) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// To call from the error callback. Only a device that went away kills the
+    /// stream, other errors such as xruns are transient.
+    pub fn error(&self, what: &str, err: cpal::StreamError) {
+        tracing::error!("{} stream error: {}", what, err);
+        if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Notices when a stream died: its device was reported as gone or its
+/// callbacks stopped coming.
+pub struct Watchdog {
+    probe: StreamProbe,
+    stall_timeout: Duration,
+    last_count: u64,
+    last_change: Instant,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::with_stall_timeout(STALL_TIMEOUT)
+    }
+
+    pub fn with_stall_timeout(stall_timeout: Duration) -> Self {
+        let probe = StreamProbe {
+            callbacks: Arc::new(AtomicU64::new(0)),
+            failed: Arc::new(AtomicBool::new(false)),
+        };
+        Self { probe, stall_timeout, last_count: 0, last_change: Instant::now() }
+    }
+
+    pub fn probe(&self) -> StreamProbe {
+        self.probe.clone()
+    }
+
+    pub fn is_dead(&mut self) -> bool {
+        if self.probe.failed.load(Ordering::Relaxed) {
+            return true;
+        }
+        let count = self.probe.callbacks.load(Ordering::Relaxed);
+        if count != self.last_count {
+            self.last_count = count;
+            self.last_change = Instant::now();
+        }
+        self.last_change.elapsed() >= self.stall_timeout
+    }
+}
+
+/// Calls `open` until it succeeds, waiting longer after each failure, and
+/// returns its result along with the number of attempts.
+///
+/// `idle` is called regularly while waiting. Gives up with the last error once
+/// the attempts are exhausted or when `shutdown` is set.
+pub fn retry_with_backoff<T>(
+    shutdown: &AtomicBool,
+    idle: impl FnMut(),
+    open: impl FnMut() -> Result<T>,
+) -> Result<(T, u32)> {
+    retry_with_delays(&BACKOFF, shutdown, idle, open)
+}
+
+/// Same as [`retry_with_backoff`], waiting for each of `delays` before the
+/// attempts.
+pub fn retry_with_delays<T>(
+    delays: &[Duration],
+    shutdown: &AtomicBool,
+    mut idle: impl FnMut(),
+    mut open: impl FnMut() -> Result<T>,
+) -> Result<(T, u32)> {
+    let mut last_error = None;
+    for (attempt, delay) in delays.iter().enumerate() {
+        let deadline = Instant::now() + *delay;
+        loop {
+            if shutdown.load(Ordering::Relaxed) {
+                anyhow::bail!("shutdown requested");
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            idle();
+            std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+        let attempt = attempt as u32 + 1;
+        match open() {
+            Ok(value) => return Ok((value, attempt)),
+            Err(e) => {
+                tracing::warn!("Attempt {} to reopen the device failed: {}", attempt, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no attempt made")))
+}
@@ -2,12 +2,68 @@
 // This source code is licensed under the license found in the
 // LICENSE file in the root directory of this source tree.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rubato::Resampler;
 
 pub const TARGET_SAMPLE_RATE: usize = 24_000;
 pub const FRAME_SIZE: usize = 1_920; // 80ms at 24kHz
 
+/// Which channel of a multichannel input is fed to the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputChannel {
+    /// Average of all the channels.
+    #[default]
+    Mix,
+    /// A single channel, 0 being the first one.
+    Index(usize),
+}
+
+impl InputChannel {
+    /// Checks that the channel exists in an input with `channels` channels.
+    pub fn validate(&self, channels: usize) -> Result<()> {
+        if let Self::Index(index) = *self {
+            if index >= channels {
+                bail!("input channel {index} does not exist, the input has {channels} channels")
+            }
+        }
+        Ok(())
+    }
+    
+    /// Mono sample from the samples of all the channels at one instant.
+    pub fn pick(&self, mut channels: impl ExactSizeIterator<Item = f32>) -> f32 {
+        match *self {
+            Self::Mix => {
+                let count = channels.len();
+                channels.sum::<f32>() / count as f32
+            }
+            Self::Index(index) => channels.nth(index).unwrap_or(0.0),
+        }
+    }
+}
+
+impl std::str::FromStr for InputChannel {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("mix") {
+            return Ok(Self::Mix);
+        }
+        match s.parse() {
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) => bail!("invalid input channel {s}, expected mix or a channel index"),
+        }
+    }
+}
+
+impl std::fmt::Display for InputChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mix => write!(f, "mix"),
+            Self::Index(index) => write!(f, "{index}"),
+        }
+    }
+}
+
 /// Streaming resampler that converts arbitrary sample rates to 24kHz
 /// and buffers frames of exactly 1920 samples (80ms)
 pub struct StreamingResampler {
@@ -17,10 +73,12 @@ pub struct StreamingResampler {
     input_len: usize,
     accumulated: Vec<f32>,
     channels: usize,
+    channel: InputChannel,
 }
 
 impl StreamingResampler {
-    pub fn new(input_sample_rate: usize, channels: usize) -> Result<Self> {
+    pub fn new(input_sample_rate: usize, channels: usize, channel: InputChannel) -> Result<Self> {
+        channel.validate(channels)?;
         let resample_ratio = TARGET_SAMPLE_RATE as f64 / input_sample_rate as f64;
         let resampler = rubato::FastFixedIn::new(
             resample_ratio,
@@ -40,6 +98,7 @@ impl StreamingResampler {
             input_len: 0,
             accumulated: Vec::new(),
             channels,
+            channel,
         })
     }
     
@@ -75,14 +134,8 @@ impl StreamingResampler {
                     None,
                 )?;
                 
-                // Downmix to mono: 0.5 * (L + R + ...)
-                for i in 0..out_len {
-                    let mut sample = 0.0;
-                    for ch in 0..self.channels {
-                        sample += self.output_buffer[ch][i];
-                    }
-                    self.accumulated.push(sample / self.channels as f32);
-                }
+                // Downmix to mono: 0.5 * (L + R + ...), or keep the selected channel
+                self.push_mono(out_len);
                 
                 self.input_len = 0;
             }
@@ -99,6 +152,13 @@ impl StreamingResampler {
         Ok(frames)
     }
     
+    fn push_mono(&mut self, out_len: usize) {
+        for i in 0..out_len {
+            let sample = self.channel.pick(self.output_buffer.iter().map(|ch| ch[i]));
+            self.accumulated.push(sample);
+        }
+    }
+    
     /// Get any remaining partial frame (used at EOF)
     pub fn flush(&mut self) -> Result<Option<[f32; FRAME_SIZE]>> {
         if self.input_len > 0 {
@@ -110,19 +170,13 @@ impl StreamingResampler {
             )?;
             
             // Downmix to mono
-            for i in 0..out_len {
-                let mut sample = 0.0;
-                for ch in 0..self.channels {
-                    sample += self.output_buffer[ch][i];
-                }
-                self.accumulated.push(sample / self.channels as f32);
-            }
+            self.push_mono(out_len);
             
             self.input_len = 0;
         }
         
         // Pad to full frame if needed
-        if self.accumulated.len() > 0 {
+        if !self.accumulated.is_empty() {
             while self.accumulated.len() < FRAME_SIZE {
                 self.accumulated.push(0.0);
             }
@@ -135,3 +189,90 @@ impl StreamingResampler {
         }
     }
 }
+
+/// Streaming resampler for the generated 24kHz mono audio, e.g. to write it
+/// at another rate.
+///
+/// The output is aligned with the input: the resampler delay is dropped and
+/// [`OutputResampler::finish`] completes the output to the resampled length.
+pub struct OutputResampler {
+    resampler: rubato::FftFixedIn<f32>,
+    output_buffer: Vec<Vec<f32>>,
+    pending: Vec<f32>,
+    /// Output samples still to drop to compensate for the resampler delay.
+    delay: usize,
+    samples_in: usize,
+    samples_out: usize,
+    ratio: f64,
+}
+
+impl OutputResampler {
+    pub fn new(output_sample_rate: usize) -> Result<Self> {
+        let resampler =
+            rubato::FftFixedIn::new(TARGET_SAMPLE_RATE, output_sample_rate, 1024, 2, 1)?;
+        let output_buffer = resampler.output_buffer_allocate(true);
+        Ok(Self {
+            delay: resampler.output_delay(),
+            resampler,
+            output_buffer,
+            pending: Vec::new(),
+            samples_in: 0,
+            samples_out: 0,
+            ratio: output_sample_rate as f64 / TARGET_SAMPLE_RATE as f64,
+        })
+    }
+
+    /// Push 24kHz samples, returns the resampled audio available so far.
+    pub fn push_samples(&mut self, pcm: &[f32]) -> Result<Vec<f32>> {
+        self.pending.extend_from_slice(pcm);
+        self.samples_in += pcm.len();
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while self.pending.len() - pos >= self.resampler.input_frames_next() {
+            let len = self.resampler.input_frames_next();
+            let (_, out_len) = self.resampler.process_into_buffer(
+                &[&self.pending[pos..pos + len]],
+                &mut self.output_buffer,
+                None,
+            )?;
+            pos += len;
+            self.emit(out_len, &mut out);
+        }
+        self.pending.drain(..pos);
+        Ok(out)
+    }
+
+    /// Returns the rest of the resampled audio.
+    pub fn finish(&mut self) -> Result<Vec<f32>> {
+        let expected = (self.samples_in as f64 * self.ratio).round() as usize;
+        let mut out = Vec::new();
+        while self.samples_out < expected {
+            let (_, out_len) = if self.pending.is_empty() {
+                self.resampler.process_partial_into_buffer(
+                    None::<&[&[f32]]>,
+                    &mut self.output_buffer,
+                    None,
+                )?
+            } else {
+                let pending = std::mem::take(&mut self.pending);
+                self.resampler.process_partial_into_buffer(
+                    Some(&[&pending]),
+                    &mut self.output_buffer,
+                    None,
+                )?
+            };
+            self.emit(out_len, &mut out);
+        }
+        let extra = self.samples_out.saturating_sub(expected);
+        out.truncate(out.len().saturating_sub(extra));
+        self.samples_out = self.samples_out.min(expected);
+        Ok(out)
+    }
+
+    fn emit(&mut self, out_len: usize, out: &mut Vec<f32>) {
+        let skip = self.delay.min(out_len);
+        self.delay -= skip;
+        out.extend_from_slice(&self.output_buffer[0][skip..out_len]);
+        self.samples_out += out_len - skip;
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+/// Steps the mimi transformers can hold, 8192 positions at 25Hz.
+pub const MIMI_MAX_STEPS: usize = 4096;
+/// Steps kept free below the cache sizes.
+const SAFETY_STEPS: usize = 64;
+/// Segments end at the first pause once they are this long (2 minutes).
+pub const DEFAULT_MIN_STEPS: usize = 1500;
+/// A pause needs this many quiet steps in a row (~1s).
+pub const PAUSE_STEPS: usize = 12;
+/// Frames with an RMS below this are considered quiet.
+pub const QUIET_RMS: f32 = 0.01;
+
+/// Why a segment ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentEnd {
+    /// Both the input and the generated audio were quiet for a while.
+    Pause,
+    /// No pause came before the caches got full.
+    Limit,
+}
+
+/// Splits a long session into segments that each start from a fresh
+/// generation state.
+///
+/// The lm kv-cache and the mimi transformers have a fixed size, about 5.5
+/// minutes of audio, so the state has to be re-initialised for sessions to run
+/// indefinitely. Segments preferably end at a pause in both the input and the
+/// generated audio so that no translation gets cut.
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    min_steps: usize,
+    max_steps: usize,
+    steps: usize,
+    quiet_steps: usize,
+}
+
+impl Segmenter {
+    /// `cache_steps` is the number of steps the lm kv-cache holds.
+    pub fn new(cache_steps: usize) -> Self {
+        let max_steps = cache_steps.min(MIMI_MAX_STEPS).saturating_sub(SAFETY_STEPS).max(1);
+        Self::with_limits(DEFAULT_MIN_STEPS.min(max_steps), max_steps)
+    }
+
+    pub fn with_limits(min_steps: usize, max_steps: usize) -> Self {
+        Self { min_steps, max_steps, steps: 0, quiet_steps: 0 }
+    }
+
+    /// Most steps a segment can last.
+    pub fn max_steps(&self) -> usize {
+        self.max_steps
+    }
+
+    /// Steps since the start of the current segment.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Records one step and returns whether the segment should end there.
+    ///
+    /// `input` and `output` are the audio consumed and generated for the step,
+    /// `has_text` whether text was generated.
+    pub fn observe(&mut self, input: &[f32], output: &[f32], has_text: bool) -> Option<SegmentEnd> {
+        self.steps += 1;
+        if !has_text && rms(input) < QUIET_RMS && rms(output) < QUIET_RMS {
+            self.quiet_steps += 1;
+        } else {
+            self.quiet_steps = 0;
+        }
+        let end = if self.steps >= self.max_steps {
+            Some(SegmentEnd::Limit)
+        } else if self.steps >= self.min_steps && self.quiet_steps >= PAUSE_STEPS {
+            Some(SegmentEnd::Pause)
+        } else {
+            None
+        };
+        if end.is_some() {
+            self.reset()
+        }
+        end
+    }
+
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.quiet_steps = 0;
+    }
+}
+
+pub(crate) fn rms(pcm: &[f32]) -> f32 {
+    if pcm.is_empty() {
+        return 0.;
+    }
+    (pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len() as f32).sqrt()
+}
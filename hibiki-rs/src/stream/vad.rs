@@ -0,0 +1,186 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Voice activity detection on the frames fed to the model, to mark the
+//! utterances of the speaker and let the model rest during long silences.
+
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use super::segment::rms;
+
+/// Duration of an input frame.
+const FRAME_DURATION: Duration = Duration::from_millis(80);
+/// Loud frames in a row that start an utterance (160ms), so that clicks and
+/// other short noises are ignored.
+pub const ONSET_FRAMES: usize = 2;
+/// The level of the quiet frames of the segmenter.
+pub const DEFAULT_THRESHOLD_DB: f32 = -40.;
+pub const DEFAULT_HANGOVER: Duration = Duration::from_millis(480);
+pub const DEFAULT_LONG_SILENCE: Duration = Duration::from_secs(3);
+
+/// What else happens when the model stops for a long silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SilenceAction {
+    #[default]
+    None,
+    /// Ends the subtitle cue and the line of transcript right away.
+    Flush,
+    /// Flushes and resets the model, so that the next utterance starts an
+    /// independent session.
+    Reset,
+}
+
+impl std::str::FromStr for SilenceAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "flush" => Ok(Self::Flush),
+            "reset" => Ok(Self::Reset),
+            _ => bail!("unknown silence action {s}, expected none, flush or reset"),
+        }
+    }
+}
+
+impl std::fmt::Display for SilenceAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Flush => write!(f, "flush"),
+            Self::Reset => write!(f, "reset"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VadConfig {
+    /// Frames with an RMS above this level, in dBFS, are speech.
+    pub threshold_db: f32,
+    /// How long an utterance lasts past its last loud frame, so that the short
+    /// pauses between words do not split it.
+    pub hangover: Duration,
+    /// Time without speech in the input nor translation in the output after
+    /// which the model stops running until the next loud frame.
+    pub long_silence: Duration,
+    pub on_long_silence: SilenceAction,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: DEFAULT_THRESHOLD_DB,
+            hangover: DEFAULT_HANGOVER,
+            long_silence: DEFAULT_LONG_SILENCE,
+            on_long_silence: SilenceAction::None,
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.threshold_db.is_finite() || self.threshold_db >= 0. {
+            bail!("the vad threshold must be below 0 dBFS, got {}", self.threshold_db)
+        }
+        if self.hangover < FRAME_DURATION {
+            bail!("the vad hangover must be at least {}ms", FRAME_DURATION.as_millis())
+        }
+        if self.long_silence <= self.hangover {
+            bail!("the vad long silence must be longer than the hangover")
+        }
+        Ok(())
+    }
+}
+
+/// Boundary noticed by the [`Vad`], steps being indexes of input frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadEvent {
+    /// An utterance started at `step`, noticed `ONSET_FRAMES` steps later.
+    UtteranceStart { step: usize },
+    /// The utterance ended at `step`, the first quiet step after it, noticed
+    /// once the hangover is over.
+    UtteranceEnd { step: usize },
+    /// The model has nothing to do from `step` on, until the next loud frame.
+    LongSilence { step: usize },
+}
+
+/// Energy based speech/silence decisions on 80ms input frames.
+#[derive(Debug, Clone)]
+pub struct Vad {
+    threshold: f32,
+    hangover_frames: usize,
+    long_silence_frames: usize,
+    step: usize,
+    speaking: bool,
+    loud_frames: usize,
+    quiet_frames: usize,
+    inactive_frames: usize,
+    idle: bool,
+}
+
+impl Vad {
+    pub fn new(config: &VadConfig) -> Self {
+        let frames = |d: Duration| d.as_millis().div_ceil(FRAME_DURATION.as_millis()) as usize;
+        Self {
+            threshold: 10f32.powf(config.threshold_db / 20.),
+            hangover_frames: frames(config.hangover).max(1),
+            long_silence_frames: frames(config.long_silence).max(1),
+            step: 0,
+            speaking: false,
+            loud_frames: 0,
+            quiet_frames: 0,
+            inactive_frames: 0,
+            idle: false,
+        }
+    }
+
+    /// Classifies the next input frame.
+    ///
+    /// `output_active` tells whether the model generated text or audible audio
+    /// for the previous frame: a long silence only starts once the translation
+    /// of the last utterance is over.
+    pub fn push(&mut self, input: &[f32], output_active: bool) -> Option<VadEvent> {
+        let step = self.step;
+        self.step += 1;
+        let loud = rms(input) > self.threshold;
+        if loud {
+            self.loud_frames += 1;
+            self.quiet_frames = 0;
+            self.idle = false;
+        } else {
+            self.loud_frames = 0;
+            self.quiet_frames += 1;
+        }
+        if loud || output_active {
+            self.inactive_frames = 0;
+        } else {
+            self.inactive_frames += 1;
+        }
+
+        if !self.speaking && self.loud_frames >= ONSET_FRAMES {
+            self.speaking = true;
+            Some(VadEvent::UtteranceStart { step: step + 1 - ONSET_FRAMES })
+        } else if self.speaking && self.quiet_frames >= self.hangover_frames {
+            self.speaking = false;
+            Some(VadEvent::UtteranceEnd { step: step + 1 - self.quiet_frames })
+        } else if !self.speaking && !self.idle && self.inactive_frames >= self.long_silence_frames {
+            self.idle = true;
+            Some(VadEvent::LongSilence { step })
+        } else {
+            None
+        }
+    }
+
+    /// Whether the last frame is part of an utterance, hangover included.
+    pub fn is_speech(&self) -> bool {
+        self.speaking
+    }
+
+    /// Whether the last frame can be skipped by the model, from a long silence
+    /// until the next loud frame.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::Result;
+use std::path::Path;
+use std::sync::mpsc;
+
+use super::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+use crate::audio_writer::{AudioWriter, OutputConfig, StereoWriter};
+
+/// Runs the thread saving the generated audio, as WAV, Ogg/Opus or FLAC
+pub fn run_audio_writer<P: AsRef<Path>>(
+    path: P,
+    config: &OutputConfig,
+    rx: mpsc::Receiver<Vec<f32>>,
+) -> Result<()> {
+    let mut writer = AudioWriter::create(path.as_ref(), config)?;
+
+    tracing::info!(
+        "Audio writer started: {:?} ({:?})",
+        path.as_ref(),
+        config.format_for(path.as_ref())
+    );
+
+    while let Ok(samples) = rx.recv() {
+        writer.write(&samples)?;
+    }
+
+    let total_samples = writer.finish()?;
+    let duration_s = total_samples as f32 / TARGET_SAMPLE_RATE as f32;
+    tracing::info!(
+        "Audio file saved: {:?} ({} samples, {:.2}s)",
+        path.as_ref(),
+        total_samples,
+        duration_s
+    );
+
+    Ok(())
+}
+
+/// Runs the thread writing the input frames and the generated audio side by
+/// side, one step at a time
+pub fn run_stereo_writer<P: AsRef<Path>>(
+    path: P,
+    rx: mpsc::Receiver<([f32; FRAME_SIZE], Vec<f32>)>,
+) -> Result<()> {
+    let mut writer = StereoWriter::create(path.as_ref())?;
+
+    tracing::info!("Stereo writer started: {:?}", path.as_ref());
+
+    while let Ok((input, output)) = rx.recv() {
+        writer.push_step(&input, &output)?;
+    }
+
+    let total_samples = writer.finish()?;
+    tracing::info!(
+        "Stereo file saved: {:?} ({} samples, {:.2}s)",
+        path.as_ref(),
+        total_samples,
+        total_samples as f32 / TARGET_SAMPLE_RATE as f32
+    );
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::{bail, Result};
+use std::io::Write;
+use std::path::Path;
+
+use crate::stream::resampler::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+
+/// Start a new cue when no text was generated for this long, 1s.
+const PAUSE_SAMPLES: usize = TARGET_SAMPLE_RATE;
+/// How long a cue stays on screen after its last piece of text (1.5s).
+const LINGER_SAMPLES: usize = 3 * TARGET_SAMPLE_RATE / 2;
+/// Break long cues at the next word boundary past this many characters.
+const MAX_CUE_CHARS: usize = 84;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+}
+
+impl SubtitleFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        match ext.as_deref() {
+            Some("srt") => Ok(Self::Srt),
+            Some("vtt") => Ok(Self::WebVtt),
+            _ => bail!("unsupported subtitle file {}, expected .srt or .vtt", path.display()),
+        }
+    }
+
+    fn timestamp(&self, sample: usize) -> String {
+        let ms = sample * 1000 / TARGET_SAMPLE_RATE;
+        let (h, m, s, ms) = (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
+        match self {
+            Self::Srt => format!("{h:02}:{m:02}:{s:02},{ms:03}"),
+            Self::WebVtt => format!("{h:02}:{m:02}:{s:02}.{ms:03}"),
+        }
+    }
+}
+
+struct Cue {
+    start: usize,
+    last: usize,
+    text: String,
+}
+
+/// Groups generated text into timed cues and writes them as SRT or WebVTT.
+///
+/// Text positions are sample offsets in the 24kHz generated audio, so the cues
+/// line up with the translated output. A cue ends on sentence punctuation, on
+/// pauses and when it gets too long.
+pub struct SubtitleWriter<W: Write> {
+    out: W,
+    format: SubtitleFormat,
+    current: Option<Cue>,
+    // A finished cue is only written once the next one starts, so that it can
+    // be shortened rather than overlap with it.
+    pending: Option<Cue>,
+    num_written: usize,
+}
+
+impl SubtitleWriter<std::io::BufWriter<std::fs::File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let format = SubtitleFormat::from_path(path.as_ref())?;
+        let file = std::fs::File::create(path.as_ref())?;
+        Self::new(std::io::BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write> SubtitleWriter<W> {
+    pub fn new(mut out: W, format: SubtitleFormat) -> Result<Self> {
+        if format == SubtitleFormat::WebVtt {
+            writeln!(out, "WEBVTT\n")?;
+        }
+        Ok(Self { out, format, current: None, pending: None, num_written: 0 })
+    }
+
+    /// Adds a piece of text generated at the given output sample offset.
+    pub fn push(&mut self, sample: usize, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if let Some(cue) = self.current.as_ref() {
+            let paused = sample.saturating_sub(cue.last) >= PAUSE_SAMPLES;
+            let new_word = text.starts_with(char::is_whitespace);
+            let too_long = new_word && cue.text.chars().count() >= MAX_CUE_CHARS;
+            if paused || too_long {
+                self.close_current()?;
+            }
+        }
+        match self.current.as_mut() {
+            Some(cue) => {
+                cue.text.push_str(text);
+                cue.last = sample;
+            }
+            None => {
+                self.flush_pending(Some(sample))?;
+                self.current = Some(Cue { start: sample, last: sample, text: text.to_string() });
+            }
+        }
+        if text.trim_end().ends_with(['.', '!', '?', '…', '。', '？', '！']) {
+            self.close_current()?;
+        }
+        Ok(())
+    }
+
+    /// Ends the current cue and writes it out without waiting for the next one,
+    /// e.g. at a long silence.
+    pub fn cut(&mut self) -> Result<()> {
+        self.close_current()?;
+        self.flush_pending(None)
+    }
+
+    /// Writes the remaining cues and flushes the output.
+    pub fn finish(mut self) -> Result<W> {
+        self.close_current()?;
+        self.flush_pending(None)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn close_current(&mut self) -> Result<()> {
+        if let Some(cue) = self.current.take() {
+            self.flush_pending(Some(cue.start))?;
+            self.pending = Some(cue);
+        }
+        Ok(())
+    }
+
+    fn flush_pending(&mut self, next_start: Option<usize>) -> Result<()> {
+        let cue = match self.pending.take() {
+            None => return Ok(()),
+            Some(cue) => cue,
+        };
+        let text = cue.text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        let mut end = cue.last + LINGER_SAMPLES;
+        if let Some(next_start) = next_start {
+            end = end.min(next_start);
+        }
+        // Keep a visible duration for text generated right before the next cue.
+        let end = end.max(cue.start + FRAME_SIZE);
+        self.num_written += 1;
+        if self.format == SubtitleFormat::Srt {
+            writeln!(self.out, "{}", self.num_written)?;
+        }
+        writeln!(
+            self.out,
+            "{} --> {}\n{text}\n",
+            self.format.timestamp(cue.start),
+            self.format.timestamp(end)
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use anyhow::Result;
+use candle::Device;
+use std::path::PathBuf;
+
+use crate::events::ModelInfo;
+use crate::gen;
+use crate::sampling::SamplingConfig;
+use crate::stream::model::StreamingModel;
+use crate::stream::processor::FrameProcessor;
+use crate::stream::resampler::FRAME_SIZE;
+use crate::stream::ModelStats;
+
+/// The model config together with the weights and tokenizer it refers to.
+#[derive(Debug, Clone)]
+pub struct ModelFiles {
+    pub config: gen::Config,
+    pub lm_model_file: PathBuf,
+    pub mimi_model_file: PathBuf,
+    pub text_tokenizer: PathBuf,
+}
+
+impl ModelFiles {
+    /// Resolves the config and model files, fetching from the hugging face hub
+    /// any file that is not provided explicitly.
+    ///
+    /// `hf_repo` also accepts the `1b` and `2b` shorthands.
+    pub fn resolve(
+        hf_repo: &str,
+        config: Option<PathBuf>,
+        lm_model_file: Option<PathBuf>,
+        mimi_model_file: Option<PathBuf>,
+        text_tokenizer: Option<PathBuf>,
+    ) -> Result<Self> {
+        let api = hf_hub::api::sync::Api::new()?;
+        let hf_repo = match hf_repo {
+            "1b" => "kyutai/hibiki-1b-rs-bf16",
+            "2b" => "kyutai/hibiki-2b-rs-bf16",
+            _ => hf_repo,
+        };
+        let repo = api.model(hf_repo.to_string());
+        let config = match config {
+            None => repo.get("config.toml")?,
+            Some(f) => f,
+        };
+        tracing::info!("loading the config");
+        let config = std::fs::read_to_string(&config)?;
+        let config: gen::Config = toml::from_str(&config)?;
+
+        let lm_model_file = match lm_model_file {
+            None => repo.get(&config.moshi_name)?,
+            Some(v) => v,
+        };
+        let mimi_model_file = match mimi_model_file {
+            None => repo.get(&config.mimi_name)?,
+            Some(v) => v,
+        };
+        let text_tokenizer = match text_tokenizer {
+            None => repo.get(&config.tokenizer_name)?,
+            Some(v) => v,
+        };
+        Ok(Self { config, lm_model_file, mimi_model_file, text_tokenizer })
+    }
+}
+
+/// Audio and text generated while processing a single input frame.
+#[derive(Debug, Clone, Default)]
+pub struct TranslatorOutput {
+    /// Translated audio, 24kHz mono. Empty while the model is warming up.
+    pub pcm: Vec<f32>,
+    /// Translated text emitted during this frame, if any.
+    pub text: Option<String>,
+}
+
+/// A loaded Hibiki model that translates a stream of 80ms audio frames.
+pub struct Translator {
+    model: StreamingModel,
+}
+
+impl Translator {
+    /// Loads the language model, mimi and the text tokenizer, sampling as
+    /// recommended by the model config with `seed` for both streams.
+    pub fn new(
+        files: &ModelFiles,
+        seed: u64,
+        cfg_alpha: Option<f64>,
+        device: &Device,
+    ) -> Result<Self> {
+        let sampling = files.config.sampling.clone().with_seed(seed);
+        Self::with_sampling(files, &sampling, cfg_alpha, device)
+    }
+
+    /// Same as [`Translator::new`] with custom text and audio sampling.
+    pub fn with_sampling(
+        files: &ModelFiles,
+        sampling: &SamplingConfig,
+        cfg_alpha: Option<f64>,
+        device: &Device,
+    ) -> Result<Self> {
+        let model = StreamingModel::new(
+            &files.config.model,
+            &files.lm_model_file,
+            &files.mimi_model_file,
+            &files.text_tokenizer,
+            sampling,
+            cfg_alpha,
+            device,
+        )?;
+        Ok(Self { model })
+    }
+
+    /// Feeds one 80ms frame of 24kHz mono audio (1920 samples).
+    pub fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<TranslatorOutput> {
+        let (pcm, text) = self.model.process_frame(pcm)?;
+        Ok(TranslatorOutput { pcm, text })
+    }
+
+    /// Per-frame processing time statistics since the model was loaded or
+    /// last reset.
+    pub fn stats(&self) -> ModelStats {
+        self.model.get_stats()
+    }
+
+    /// Starts a new, independent session without reloading the weights.
+    pub fn reset(&mut self) {
+        self.model.reset()
+    }
+
+    /// Returns a translator in a fresh state that shares the weights with this
+    /// one, so that several sessions can run side by side.
+    pub fn fork(&self) -> Self {
+        Self { model: self.model.fork() }
+    }
+}
+
+impl FrameProcessor for Translator {
+    fn process_frame(&mut self, pcm: &[f32; FRAME_SIZE]) -> Result<(Vec<f32>, Option<String>)> {
+        self.model.process_frame(pcm)
+    }
+
+    fn stats(&self) -> ModelStats {
+        self.model.get_stats()
+    }
+
+    fn reset(&mut self) {
+        self.model.reset()
+    }
+
+    fn info(&self) -> ModelInfo {
+        self.model.info()
+    }
+
+    fn last_text_tokens(&self) -> &[(u32, String)] {
+        self.model.last_text_tokens()
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TempDir};
+    use hibiki::audio_writer::{
+        AudioWriter, FlacConfig, FlacWriter, OggOpusWriter, OpusConfig, OutputConfig, OutputFormat,
+        WavConfig, WavSampleFormat, WavWriter,
+    };
+    use hibiki::stream::resampler::OutputResampler;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    fn tone(len: usize) -> Vec<f32> {
+        common::tone(24_000, len).collect()
+    }
+
+    /// Encodes one second of audio, then checks the stream structure and that
+    /// it decodes to the same duration.
+    fn check_opus_roundtrip(config: &OpusConfig) {
+        let mut writer = OggOpusWriter::new(Cursor::new(vec![]), config).unwrap();
+        for chunk in tone(24_000).chunks(1920) {
+            writer.write(chunk).unwrap();
+        }
+        assert_eq!(writer.samples(), 24_000);
+        let data = writer.finish().unwrap().into_inner();
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(data));
+        let head = reader.read_packet().unwrap().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!(head.data[9], 1);
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        let tags = reader.read_packet().unwrap().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+
+        let mut decoder = opus_rs::OpusDecoder::new(48_000, 1).unwrap();
+        let mut pcm = [0f32; 5760];
+        let (mut decoded, mut last) = (0, None);
+        while let Some(packet) = reader.read_packet().unwrap() {
+            decoded += decoder.decode(&packet.data, 5760, &mut pcm).unwrap() as u64;
+            last = Some(packet);
+        }
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        // The final granule position trims the stream to the exact duration.
+        assert_eq!(last.absgp_page(), pre_skip + 48_000);
+        assert!(decoded >= pre_skip + 48_000 && decoded < pre_skip + 48_000 + 960, "{decoded}");
+    }
+
+    #[test]
+    fn test_opus_roundtrip() {
+        check_opus_roundtrip(&OpusConfig::default());
+        check_opus_roundtrip(&OpusConfig { bitrate: 16_000, sample_rate: 16_000 });
+        check_opus_roundtrip(&OpusConfig { bitrate: 64_000, sample_rate: 48_000 });
+    }
+
+    /// Decodes a FLAC file, checking its checksum, returns the samples and the
+    /// stream info bit depth.
+    fn decode_flac(data: Vec<u8>) -> (Vec<i32>, u32) {
+        use symphonia::core::audio::SampleBuffer;
+        use symphonia::core::codecs::DecoderOptions;
+        use symphonia::core::io::MediaSourceStream;
+
+        let source = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+        let mut format = symphonia::default::get_probe()
+            .format(&Default::default(), source, &Default::default(), &Default::default())
+            .unwrap()
+            .format;
+        let params = format.default_track().unwrap().codec_params.clone();
+        assert_eq!(params.sample_rate, Some(24_000));
+        let bits = params.bits_per_sample.unwrap();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions { verify: true })
+            .unwrap();
+        let mut samples = vec![];
+        while let Ok(packet) = format.next_packet() {
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend(buffer.samples().iter().map(|s| s >> (32 - bits)));
+        }
+        assert_eq!(decoder.finalize().verify_ok, Some(true));
+        assert_eq!(params.n_frames, Some(samples.len() as u64));
+        (samples, bits)
+    }
+
+    #[test]
+    fn test_flac_roundtrip() {
+        let pcm = tone(10_000);
+        for bits in [16, 24] {
+            let mut writer =
+                FlacWriter::new(Cursor::new(vec![]), &FlacConfig { bits_per_sample: bits })
+                    .unwrap();
+            for chunk in pcm.chunks(1920) {
+                writer.write(chunk).unwrap();
+            }
+            assert_eq!(writer.samples(), 10_000);
+            let (samples, decoded_bits) = decode_flac(writer.finish().unwrap().into_inner());
+            assert_eq!(decoded_bits, bits);
+            assert_eq!(samples.len(), pcm.len());
+            // The dither and the truncation each add at most one least
+            // significant bit.
+            let scale = (1 << (bits - 1)) as f32;
+            for (&decoded, &original) in samples.iter().zip(pcm.iter()) {
+                assert!((decoded as f32 - original * scale).abs() <= 3., "{decoded} {original}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_wav_formats() {
+        let dir = TempDir::new("wav");
+        let pcm = tone(24_000);
+        for (sample_format, bits) in
+            [(WavSampleFormat::I16, 16), (WavSampleFormat::I24, 24), (WavSampleFormat::F32, 32)]
+        {
+            for sample_rate in [24_000, 48_000] {
+                let path = dir.join(&format!("{sample_format}_{sample_rate}.wav"));
+                let mut writer =
+                    WavWriter::create(&path, &WavConfig { sample_format, sample_rate }).unwrap();
+                for chunk in pcm.chunks(1920) {
+                    writer.write(chunk).unwrap();
+                }
+                assert_eq!(writer.finish().unwrap(), 24_000);
+
+                let mut reader = hound::WavReader::open(&path).unwrap();
+                let spec = reader.spec();
+                assert_eq!((spec.sample_rate, spec.bits_per_sample), (sample_rate, bits));
+                let samples: Vec<f32> = match sample_format {
+                    WavSampleFormat::F32 => reader.samples::<f32>().map(|s| s.unwrap()).collect(),
+                    _ => {
+                        let scale = (1 << (bits - 1)) as f32;
+                        reader.samples::<i32>().map(|s| s.unwrap() as f32 / scale).collect()
+                    }
+                };
+                assert_eq!(samples.len(), sample_rate as usize);
+                if sample_rate == 24_000 {
+                    let tolerance = if bits == 32 { 0. } else { 3. / (1 << (bits - 1)) as f32 };
+                    for (&decoded, &original) in samples.iter().zip(pcm.iter()) {
+                        assert!((decoded - original).abs() <= tolerance, "{decoded} {original}");
+                    }
+                } else {
+                    // Every other sample of the resampled tone is the original one.
+                    for (i, &original) in pcm.iter().enumerate().skip(100).take(23_800) {
+                        assert!((samples[2 * i] - original).abs() < 0.01, "{i}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let config = OpusConfig { sample_rate: 44_100, ..Default::default() };
+        assert!(OggOpusWriter::new(Cursor::new(vec![]), &config).is_err());
+        let config = OpusConfig { bitrate: 100, ..Default::default() };
+        assert!(config.validate().is_err());
+        let config = FlacConfig { bits_per_sample: 20 };
+        assert!(FlacWriter::new(Cursor::new(vec![]), &config).is_err());
+        let config = WavConfig { sample_rate: 4_000, ..Default::default() };
+        assert!(config.validate().is_err());
+        assert_eq!("F32".parse::<WavSampleFormat>().unwrap(), WavSampleFormat::F32);
+        assert!("u8".parse::<WavSampleFormat>().is_err());
+    }
+
+    #[test]
+    fn test_output_format_selection() {
+        assert_eq!(OutputFormat::from_path(Path::new("a.OPUS")), Some(OutputFormat::Opus));
+        assert_eq!(OutputFormat::from_path(Path::new("a.ogg")), Some(OutputFormat::Opus));
+        assert_eq!(OutputFormat::from_path(Path::new("a.wav")), Some(OutputFormat::Wav));
+        assert_eq!(OutputFormat::from_path(Path::new("a.flac")), Some(OutputFormat::Flac));
+        assert_eq!(OutputFormat::from_path(Path::new("a.out")), None);
+        assert_eq!("opus".parse::<OutputFormat>().unwrap(), OutputFormat::Opus);
+        assert!("mp3".parse::<OutputFormat>().is_err());
+
+        let dir = TempDir::new("writer");
+        let magic = |name: &str, config: &OutputConfig| {
+            let path = dir.join(name);
+            let mut writer = AudioWriter::create(&path, config).unwrap();
+            writer.write(&tone(4800)).unwrap();
+            assert_eq!(writer.finish().unwrap(), 4800);
+            std::fs::read(&path).unwrap()[..4].to_vec()
+        };
+        let default = OutputConfig::default();
+        assert_eq!(magic("a.opus", &default), b"OggS");
+        assert_eq!(magic("a.wav", &default), b"RIFF");
+        assert_eq!(magic("a.flac", &default), b"fLaC");
+        assert_eq!(magic("a.bin", &default), b"RIFF");
+        let opus = OutputConfig { format: Some(OutputFormat::Opus), ..Default::default() };
+        assert_eq!(magic("b.wav", &opus), b"OggS");
+    }
+
+    #[test]
+    fn test_output_resampler_is_aligned() {
+        let mut input = vec![0f32; 24_000];
+        input[6_000] = 1.;
+        let mut resampler = OutputResampler::new(48_000).unwrap();
+        let mut output = vec![];
+        for chunk in input.chunks(1920) {
+            output.extend(resampler.push_samples(chunk).unwrap());
+        }
+        output.extend(resampler.finish().unwrap());
+        assert_eq!(output.len(), 48_000);
+        let peak = (0..output.len()).max_by(|&a, &b| output[a].total_cmp(&output[b])).unwrap();
+        assert!(peak.abs_diff(12_000) <= 1, "{peak}");
+    }
+}
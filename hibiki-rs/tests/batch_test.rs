@@ -0,0 +1,106 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{tone, write_wav, TempDir};
+    use hibiki::batch::{read_manifest, ManifestItem};
+    use hibiki::stream::EchoProcessor;
+    use std::path::PathBuf;
+
+    fn item(input: &str, output: &str) -> ManifestItem {
+        ManifestItem { input: PathBuf::from(input), output: PathBuf::from(output) }
+    }
+
+    #[test]
+    fn test_read_jsonl_and_csv_manifests() {
+        let dir = TempDir::new("manifest");
+
+        let jsonl = dir.join("manifest.jsonl");
+        std::fs::write(
+            &jsonl,
+            "{\"input\": \"a.mp3\", \"output\": \"a.wav\"}\n\n{\"input\": \"b.mp3\", \"output\": \"b.wav\"}\n",
+        )
+        .unwrap();
+        let csv = dir.join("manifest.csv");
+        std::fs::write(&csv, "input,output\na.mp3,a.wav\nb.mp3,b.wav\n").unwrap();
+
+        let expected = vec![item("a.mp3", "a.wav"), item("b.mp3", "b.wav")];
+        assert_eq!(read_manifest(&jsonl).unwrap(), expected);
+        assert_eq!(read_manifest(&csv).unwrap(), expected);
+
+        let bad = dir.join("bad.jsonl");
+        std::fs::write(&bad, "{\"input\": \"a.mp3\"}\n").unwrap();
+        let err = read_manifest(&bad).unwrap_err();
+        assert!(format!("{err:#}").contains("bad.jsonl:1"), "{err:#}");
+    }
+
+    #[test]
+    fn test_batch_skips_done_items_and_isolates_failures() {
+        let dir = TempDir::new("batch");
+        let input = dir.join("input.wav");
+        // 3s, the echo processor emits text on frames 0 and 25.
+        write_wav(&input, 24_000, tone(24_000, 3 * 24_000));
+        let garbage = dir.join("garbage.wav");
+        std::fs::write(&garbage, b"not an audio file").unwrap();
+        let done = dir.join("done.wav");
+        std::fs::write(&done, b"already translated").unwrap();
+
+        let items = [
+            (&input, dir.join("a.wav")),
+            (&garbage, dir.join("b.wav")),
+            (&input, done.clone()),
+            (&input, dir.join("c.wav")),
+        ];
+        let manifest = dir.join("manifest.jsonl");
+        let lines: Vec<String> = items
+            .iter()
+            .map(|(input, output)| {
+                serde_json::json!({ "input": input, "output": output }).to_string()
+            })
+            .collect();
+        std::fs::write(&manifest, lines.join("\n")).unwrap();
+
+        let args =
+            hibiki::batch::Args { manifest, results: dir.join("results.jsonl"), overwrite: false };
+        hibiki::batch::run(&args, EchoProcessor::new(2)).unwrap();
+
+        let results: Vec<serde_json::Value> = std::fs::read_to_string(&args.results)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        // The existing output is skipped, without a record.
+        assert_eq!(results.len(), 3);
+        assert_eq!(std::fs::read(&done).unwrap(), b"already translated");
+        // The failure is recorded and the next item still runs.
+        assert_eq!(results[1]["ok"], false);
+        assert_eq!(results[1]["input"], garbage.to_str().unwrap());
+        assert!(results[1]["error"].as_str().unwrap().contains("format"));
+        assert!(!dir.join("b.wav").exists());
+        // Items are translated from a fresh state, so identical inputs give the
+        // same transcript.
+        for (result, output) in [(&results[0], "a.wav"), (&results[2], "c.wav")] {
+            assert_eq!(result["ok"], true);
+            assert_eq!(result["transcript"], " [0] [25]");
+            assert!(hound::WavReader::open(dir.join(output)).unwrap().len() > 0);
+        }
+
+        // An output that cannot be written is a failure leaving no partial
+        // file behind, here a directory the translation cannot replace.
+        let blocked = dir.join("blocked.wav");
+        std::fs::create_dir_all(blocked.join("inside")).unwrap();
+        let line = serde_json::json!({ "input": input, "output": blocked }).to_string();
+        std::fs::write(&args.manifest, line).unwrap();
+        let args = hibiki::batch::Args { overwrite: true, ..args };
+        hibiki::batch::run(&args, EchoProcessor::new(2)).unwrap();
+        let results = std::fs::read_to_string(&args.results).unwrap();
+        let last: serde_json::Value =
+            serde_json::from_str(results.lines().last().unwrap()).unwrap();
+        assert_eq!(last["ok"], false);
+        assert!(!dir.join("blocked.wav.partial").exists());
+    }
+}
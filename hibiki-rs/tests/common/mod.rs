@@ -0,0 +1,94 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+//! Fixtures shared by the integration tests, each of them using a part only.
+#![allow(dead_code)]
+
+use hibiki::stream::{run_with_shutdown, EchoProcessor, StreamConfig};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Frames of delay of the echo processor standing in for the model.
+pub const ECHO_DELAY: usize = 2;
+
+/// A temporary directory, removed when dropped so that failed tests leave no
+/// files behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `hibiki_<name>_<pid>` in the system temporary directory.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("hibiki_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `num_samples` of a 440Hz tone at 0.3 of the full scale.
+pub fn tone(sample_rate: u32, num_samples: usize) -> impl Iterator<Item = f32> {
+    (0..num_samples).map(move |i| {
+        let t = i as f32 / sample_rate as f32;
+        0.3 * (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+    })
+}
+
+/// Encodes the interleaved `samples`, in [-1, 1], as a 16-bit WAV file.
+pub fn wav_bytes(
+    sample_rate: u32,
+    channels: u16,
+    samples: impl IntoIterator<Item = f32>,
+) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = std::io::Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+    for v in samples {
+        writer.write_sample((v * i16::MAX as f32) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    cursor.into_inner()
+}
+
+/// Writes `samples`, in [-1, 1], to a 16-bit mono WAV file.
+pub fn write_wav(path: &Path, sample_rate: u32, samples: impl IntoIterator<Item = f32>) {
+    std::fs::write(path, wav_bytes(sample_rate, 1, samples)).unwrap();
+}
+
+/// Streams with the echo processor in place of the model, until the input
+/// ends.
+pub fn run_echo(config: StreamConfig) {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    run_with_shutdown(config, EchoProcessor::new(ECHO_DELAY), shutdown).unwrap();
+}
+
+/// Writes the 24kHz `input` to `input.wav` in a new temporary directory and
+/// streams it with the echo processor, without the speaker. `config` gives
+/// the other settings, e.g. the outputs to write to the directory, which is
+/// returned.
+pub fn run_echo_on_file(
+    name: &str,
+    input: impl IntoIterator<Item = f32>,
+    config: impl FnOnce(&TempDir) -> StreamConfig,
+) -> TempDir {
+    let dir = TempDir::new(name);
+    let input_file = dir.join("input.wav");
+    write_wav(&input_file, 24_000, input);
+    run_echo(StreamConfig { input_file: Some(input_file), disable_speaker: true, ..config(&dir) });
+    dir
+}
@@ -0,0 +1,120 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{run_echo_on_file, tone};
+    use hibiki::stream::StreamConfig;
+    use serde_json::Value;
+
+    #[test]
+    fn test_stream_writes_events() {
+        // 3s of silence + 0.5s of padding = 44 frames.
+        let silence = std::iter::repeat_n(0., 3 * 24_000);
+        let dir = run_echo_on_file("events", silence, |dir| StreamConfig {
+            no_pace: true,
+            events: Some(dir.join("events.jsonl")),
+            ..Default::default()
+        });
+
+        let events: Vec<Value> = std::fs::read_to_string(dir.join("events.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let of_kind =
+            |kind: &str| -> Vec<&Value> { events.iter().filter(|e| e["event"] == kind).collect() };
+
+        assert_eq!(events[0]["event"], "session_start");
+        assert_eq!(events[0]["model"]["name"], "echo");
+        assert_eq!(events[0]["sample_rate"], 24_000);
+        assert_eq!(events.last().unwrap()["event"], "session_end");
+        assert_eq!(events.last().unwrap()["frames"], 44);
+        assert_eq!(events.last().unwrap()["samples_generated"], 42 * hibiki::FRAME_SIZE);
+
+        let frames = of_kind("frame");
+        assert_eq!(frames.len(), 44);
+        assert!(frames.iter().enumerate().all(|(i, f)| f["step"] == i));
+
+        // Audio chunks are contiguous and start once the echo delay is over.
+        let audio = of_kind("audio");
+        assert_eq!(audio.len(), 42);
+        assert_eq!(audio[0]["step"], 2);
+        for (i, chunk) in audio.iter().enumerate() {
+            assert_eq!(chunk["offset"], i * hibiki::FRAME_SIZE);
+            assert_eq!(chunk["length"], hibiki::FRAME_SIZE);
+        }
+
+        let text = of_kind("text");
+        assert_eq!(text.len(), 2);
+        assert_eq!(text[1]["step"], 25);
+        assert_eq!(text[1]["piece"], " [25]");
+        assert_eq!(text[1]["sample"], 24 * hibiki::FRAME_SIZE);
+        assert!(text[1]["token"].is_null());
+    }
+
+    #[test]
+    fn test_vad_marks_utterances_and_pauses_the_model() {
+        use hibiki::stream::{SilenceAction, VadConfig};
+        use std::time::Duration;
+
+        // Two 1s tones separated by 2.5s of silence.
+        let input = tone(24_000, 11 * 12_000).enumerate().map(|(i, v)| {
+            let loud = (1..3).contains(&(i / 12_000)) || (8..10).contains(&(i / 12_000));
+            if loud {
+                v
+            } else {
+                0.
+            }
+        });
+        let vad = VadConfig {
+            long_silence: Duration::from_secs(1),
+            on_long_silence: SilenceAction::Reset,
+            ..Default::default()
+        };
+        let dir = run_echo_on_file("vad_events", input, |dir| StreamConfig {
+            no_pace: true,
+            events: Some(dir.join("events.jsonl")),
+            vad: Some(vad),
+            ..Default::default()
+        });
+
+        let events: Vec<Value> = std::fs::read_to_string(dir.join("events.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let of_kind =
+            |kind: &str| -> Vec<&Value> { events.iter().filter(|e| e["event"] == kind).collect() };
+        let steps = |kind: &str| -> Vec<u64> {
+            of_kind(kind).iter().map(|e| e["step"].as_u64().unwrap()).collect()
+        };
+
+        // The tones span steps 6.25-18.75 and 50-62.5.
+        let starts = steps("utterance_start");
+        let ends = steps("utterance_end");
+        assert_eq!(starts.len(), 2);
+        assert_eq!(ends.len(), 2);
+        assert!((6..=7).contains(&starts[0]) && (18..=19).contains(&ends[0]));
+        assert!((50..=51).contains(&starts[1]) && (62..=63).contains(&ends[1]));
+        // The model idles once the echo of the first utterance and its text are
+        // over, then gets reset and starts again with the second one.
+        let silences = of_kind("long_silence");
+        assert_eq!(silences.len(), 1);
+        assert_eq!(silences[0]["action"], "reset");
+        let silence_step = silences[0]["step"].as_u64().unwrap();
+        assert!(silence_step > ends[0] && silence_step < starts[1]);
+        let pieces = of_kind("text");
+        assert_eq!(pieces.last().unwrap()["piece"], " [0]");
+        assert_eq!(pieces.last().unwrap()["step"], starts[1]);
+
+        // Idle steps generate silence, only the echo delay after the start
+        // and after the reset generates nothing.
+        let frames = steps("frame").len();
+        let end = events.last().unwrap();
+        assert_eq!(end["samples_generated"], (frames - 4) * hibiki::FRAME_SIZE);
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{tone, wav_bytes};
+    use hibiki::http;
+    use hibiki::stream::{EchoProcessor, FrameProcessor, ModelStats};
+    use hibiki::FRAME_SIZE;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    struct Server {
+        addr: SocketAddr,
+        shutdown: Arc<AtomicBool>,
+        handle: Option<JoinHandle<()>>,
+    }
+
+    impl Server {
+        fn start(max_sync_seconds: f32) -> Self {
+            Self::start_with(max_sync_seconds, EchoProcessor::new(2))
+        }
+
+        fn start_with<P: FrameProcessor + Send + 'static>(
+            max_sync_seconds: f32,
+            processor: P,
+        ) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let server_shutdown = shutdown.clone();
+            let handle = std::thread::spawn(move || {
+                http::serve(listener, processor, max_sync_seconds, server_shutdown).unwrap()
+            });
+            Self { addr, shutdown, handle: Some(handle) }
+        }
+
+        /// Sends a request and returns the status, headers and body, using
+        /// HTTP/1.0 to keep the responses simple to parse.
+        fn request(&self, method: &str, path: &str, body: &[u8]) -> Response {
+            let mut stream = TcpStream::connect(self.addr).unwrap();
+            write!(
+                stream,
+                "{method} {path} HTTP/1.0\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+            let mut response = vec![];
+            stream.read_to_end(&mut response).unwrap();
+            let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8(response[..split].to_vec()).unwrap();
+            let mut lines = head.lines();
+            let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+            let headers = lines
+                .filter_map(|l| l.split_once(": "))
+                .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+                .collect();
+            Response { status, headers, body: response[split + 4..].to_vec() }
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::Relaxed);
+            if let Some(handle) = self.handle.take() {
+                handle.join().unwrap()
+            }
+        }
+    }
+
+    struct Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Response {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+        }
+
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+
+        fn wav_samples(&self) -> usize {
+            let reader = hound::WavReader::new(std::io::Cursor::new(&self.body)).unwrap();
+            assert_eq!(reader.spec().sample_rate, 24_000);
+            assert_eq!(reader.spec().channels, 1);
+            reader.len() as usize
+        }
+    }
+
+    /// A WAV file holding a 440Hz tone.
+    fn tone_wav(sample_rate: u32, seconds: f32) -> Vec<u8> {
+        wav_bytes(sample_rate, 1, tone(sample_rate, (sample_rate as f32 * seconds) as usize))
+    }
+
+    /// An echo taking 50ms per frame, to keep the worker busy.
+    struct SlowEcho(EchoProcessor);
+
+    impl FrameProcessor for SlowEcho {
+        fn process_frame(
+            &mut self,
+            pcm: &[f32; FRAME_SIZE],
+        ) -> anyhow::Result<(Vec<f32>, Option<String>)> {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            self.0.process_frame(pcm)
+        }
+
+        fn stats(&self) -> ModelStats {
+            self.0.stats()
+        }
+
+        fn reset(&mut self) {
+            self.0.reset()
+        }
+    }
+
+    fn percent_decode(encoded: &str) -> String {
+        let mut bytes = vec![];
+        let mut rest = encoded.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            if b == b'%' {
+                let hex = std::str::from_utf8(&tail[..2]).unwrap();
+                bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                rest = &tail[2..];
+            } else {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn wait_for_job(server: &Server, id: u64) -> serde_json::Value {
+        for _ in 0..500 {
+            let info = server.request("GET", &format!("/jobs/{id}"), b"").json();
+            if info["status"] == "done" || info["status"] == "failed" {
+                return info;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("job {id} did not finish")
+    }
+
+    #[test]
+    fn test_translate_waits_for_the_result() {
+        let server = Server::start(60.);
+        let response = server.request("POST", "/translate", &tone_wav(16_000, 1.));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("audio/wav"));
+        assert!(response.wav_samples() > 20_000);
+
+        let id: u64 = response.header("x-job-id").unwrap().parse().unwrap();
+        let info = server.request("GET", &format!("/jobs/{id}"), b"").json();
+        assert_eq!(info["status"], "done");
+        // The echo processor produces a text piece on its first frame.
+        let transcript = info["transcript"].as_str().unwrap();
+        assert!(!transcript.is_empty());
+        // The response has it too, percent-encoded in a header.
+        assert_eq!(percent_decode(response.header("x-transcript").unwrap()), transcript);
+        assert_eq!(response.header("x-transcript-truncated"), None);
+    }
+
+    #[test]
+    fn test_long_and_async_requests_get_a_job() {
+        let server = Server::start(0.5);
+        let short = tone_wav(24_000, 0.25);
+        let long = tone_wav(24_000, 1.);
+        let ids: Vec<u64> = [
+            server.request("POST", "/translate?async=true", &short),
+            server.request("POST", "/translate", &long),
+            server.request("POST", "/translate?async=1", &long),
+        ]
+        .iter()
+        .map(|response| {
+            assert_eq!(response.status, 202);
+            let info = response.json();
+            let id = info["id"].as_u64().unwrap();
+            assert_eq!(response.header("location"), Some(format!("/jobs/{id}").as_str()));
+            id
+        })
+        .collect();
+
+        for id in ids {
+            let info = wait_for_job(&server, id);
+            assert_eq!(info["status"], "done");
+            let audio = server.request("GET", &format!("/jobs/{id}/audio"), b"");
+            assert_eq!(audio.status, 200);
+            assert!(audio.wav_samples() > 0);
+        }
+    }
+
+    #[test]
+    fn test_more_requests_than_threads() {
+        let server = Server::start(60.);
+        let wav = tone_wav(24_000, 0.25);
+        std::thread::scope(|s| {
+            let requests: Vec<_> =
+                (0..20).map(|_| s.spawn(|| server.request("POST", "/translate", &wav))).collect();
+            for request in requests {
+                let response = request.join().unwrap();
+                assert_eq!(response.status, 200);
+                assert!(response.wav_samples() > 0);
+            }
+        });
+    }
+
+    #[test]
+    fn test_full_queue_is_rejected() {
+        let server = Server::start_with(60., SlowEcho(EchoProcessor::new(2)));
+        let wav = tone_wav(24_000, 1.);
+        let statuses: Vec<u16> =
+            (0..24).map(|_| server.request("POST", "/translate?async=true", &wav).status).collect();
+        // One job is running and 16 wait, the others are turned away.
+        let accepted = statuses.iter().filter(|&&s| s == 202).count();
+        assert!((17..=18).contains(&accepted), "{statuses:?}");
+        assert!(statuses.iter().all(|&s| s == 202 || s == 503));
+        // Rejected jobs are forgotten rather than left queued.
+        let last_id = statuses.len() as u64 - 1;
+        assert_eq!(server.request("GET", &format!("/jobs/{last_id}"), b"").status, 404);
+    }
+
+    #[test]
+    fn test_errors() {
+        let server = Server::start(60.);
+        let response = server.request("POST", "/translate", b"not an audio file");
+        assert_eq!(response.status, 400);
+        assert!(response.json()["error"].as_str().unwrap().contains("decode"));
+        assert_eq!(server.request("GET", "/jobs/42", b"").status, 404);
+        assert_eq!(server.request("GET", "/jobs/42/audio", b"").status, 404);
+        assert_eq!(server.request("GET", "/translate", b"").status, 405);
+        assert_eq!(server.request("GET", "/", b"").status, 404);
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+#[cfg(test)]
+mod tests {
+    use hibiki::stream::recovery::{retry_with_delays, Watchdog};
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    const DELAYS: [Duration; 3] = [Duration::from_millis(5); 3];
+
+    #[test]
+    fn test_watchdog_notices_stalled_callbacks() {
+        let mut watchdog = Watchdog::with_stall_timeout(Duration::from_millis(500));
+        let probe = watchdog.probe();
+        for _ in 0..5 {
+            probe.tick();
+            std::thread::sleep(Duration::from_millis(40));
+            assert!(!watchdog.is_dead());
+        }
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(watchdog.is_dead());
+    }
+
+    #[test]
+    fn test_watchdog_notices_lost_devices() {
+        let mut watchdog = Watchdog::with_stall_timeout(Duration::from_secs(60));
+        let probe = watchdog.probe();
+        // Other stream errors, e.g. xruns, are transient.
+        probe.error(
+            "test",
+            cpal::StreamError::BackendSpecific {
+                err: cpal::BackendSpecificError { description: "xrun".to_string() },
+            },
+        );
+        assert!(!watchdog.is_dead());
+        probe.error("test", cpal::StreamError::DeviceNotAvailable);
+        assert!(watchdog.is_dead());
+    }
+
+    #[test]
+    fn test_retry_counts_attempts() {
+        let shutdown = AtomicBool::new(false);
+        let mut calls = 0;
+        let result = retry_with_delays(
+            &DELAYS,
+            &shutdown,
+            || {},
+            || {
+                calls += 1;
+                if calls < 3 {
+                    anyhow::bail!("not yet")
+                }
+                Ok(calls)
+            },
+        );
+        assert_eq!(result.unwrap(), (3, 3));
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let shutdown = AtomicBool::new(false);
+        let mut calls = 0;
+        let result: anyhow::Result<((), u32)> = retry_with_delays(
+            &DELAYS,
+            &shutdown,
+            || {},
+            || {
+                calls += 1;
+                anyhow::bail!("attempt {calls}")
+            },
+        );
+        assert_eq!(calls, 3);
+        assert_eq!(result.unwrap_err().to_string(), "attempt 3");
+    }
+
+    #[test]
+    fn test_retry_stops_on_shutdown() {
+        let shutdown = AtomicBool::new(true);
+        let mut calls = 0;
+        let result = retry_with_delays(
+            &DELAYS,
+            &shutdown,
+            || {},
+            || {
+                calls += 1;
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(calls, 0);
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+#[cfg(test)]
+mod tests {
+    use candle_transformers::generation::Sampling;
+    use hibiki::sampling::{SamplingConfig, SamplingOverrides, SamplingParams, DEFAULT_SEED};
+
+    #[test]
+    fn test_default_sampling() {
+        let config = SamplingConfig::default();
+        assert_eq!(config.text.sampling(), Sampling::TopK { k: 25, temperature: 0.8 });
+        assert_eq!(config.audio.sampling(), Sampling::TopK { k: 250, temperature: 0.8 });
+        assert_eq!(config.text.seed, DEFAULT_SEED);
+        assert_eq!(config.audio.seed, DEFAULT_SEED);
+    }
+
+    #[test]
+    fn test_sampling_config_from_toml() {
+        let config: SamplingConfig = toml::from_str(
+            r#"
+            [text]
+            temperature = 0.6
+            top_k = 50
+            top_p = 0.9
+            seed = 42
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.text.sampling(),
+            Sampling::TopKThenTopP { k: 50, p: 0.9, temperature: 0.6 }
+        );
+        assert_eq!(config.text.seed, 42);
+        // Sections that are not given keep the defaults.
+        assert_eq!(config.audio, SamplingConfig::default().audio);
+
+        // Fields that are not given keep the defaults of their stream.
+        let config: SamplingConfig = toml::from_str("[audio]\ntemperature = 0.7\n").unwrap();
+        assert_eq!(config.audio.sampling(), Sampling::TopK { k: 250, temperature: 0.7 });
+        assert_eq!(config.audio.seed, DEFAULT_SEED);
+        let config: SamplingConfig = toml::from_str("[text]\ntop_k = 10\n").unwrap();
+        assert_eq!(config.text.sampling(), Sampling::TopK { k: 10, temperature: 0.8 });
+        let config: SamplingConfig = toml::from_str("[text]\ntop_k = 0\n").unwrap();
+        assert_eq!(config.text.sampling(), Sampling::All { temperature: 0.8 });
+        let config: SamplingConfig = toml::from_str("[text]\ntop_p = 1.0\n").unwrap();
+        assert_eq!(config.text.top_p, None);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let config: SamplingConfig =
+            toml::from_str("[text]\ntop_p = 0.9\ngreedy = true\n").unwrap();
+        let mut text = config.text.clone();
+        // Overrides set from the command line apply on top of the config.
+        let overrides =
+            SamplingOverrides { top_p: Some(1.), greedy: Some(false), ..Default::default() };
+        overrides.apply(&mut text);
+        assert_eq!(text.sampling(), Sampling::TopK { k: 25, temperature: 0.8 });
+        SamplingOverrides { greedy: Some(true), ..Default::default() }.apply(&mut text);
+        assert_eq!(text.sampling(), Sampling::ArgMax);
+        // Fields that are not set are left as they are.
+        let mut audio = config.audio.clone();
+        SamplingOverrides::default().apply(&mut audio);
+        assert_eq!(audio, config.audio);
+    }
+
+    #[test]
+    fn test_greedy_and_validation() {
+        let mut params = SamplingParams::top_k(25, 0.8);
+        params.greedy = true;
+        assert_eq!(params.sampling(), Sampling::ArgMax);
+        assert_eq!(SamplingParams::top_k(25, 0.).sampling(), Sampling::ArgMax);
+
+        let mut config = SamplingConfig::default().with_seed(7);
+        assert_eq!((config.text.seed, config.audio.seed), (7, 7));
+        assert!(config.validate().is_ok());
+        config.audio.top_p = Some(1.5);
+        assert!(config.validate().is_err());
+        config.audio.top_p = None;
+        config.text.top_k = Some(0);
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+#[cfg(test)]
+mod tests {
+    use hibiki::stream::segment::{SegmentEnd, Segmenter, PAUSE_STEPS};
+    use hibiki::FRAME_SIZE;
+
+    const LOUD: [f32; FRAME_SIZE] = [0.1; FRAME_SIZE];
+    const QUIET: [f32; FRAME_SIZE] = [0.; FRAME_SIZE];
+
+    #[test]
+    fn test_segment_ends_at_pause() {
+        let mut segmenter = Segmenter::with_limits(5, 100);
+        for _ in 0..4 {
+            assert_eq!(segmenter.observe(&LOUD, &LOUD, true), None);
+        }
+        // The translation is still being generated after the input stopped.
+        for _ in 0..6 {
+            assert_eq!(segmenter.observe(&QUIET, &LOUD, false), None);
+        }
+        for _ in 0..PAUSE_STEPS - 1 {
+            assert_eq!(segmenter.observe(&QUIET, &QUIET, false), None);
+        }
+        assert_eq!(segmenter.observe(&QUIET, &QUIET, false), Some(SegmentEnd::Pause));
+        assert_eq!(segmenter.steps(), 0);
+    }
+
+    #[test]
+    fn test_pauses_are_ignored_in_short_segments() {
+        let mut segmenter = Segmenter::with_limits(50, 100);
+        for _ in 0..49 {
+            assert_eq!(segmenter.observe(&QUIET, &QUIET, false), None);
+        }
+        assert_eq!(segmenter.observe(&QUIET, &QUIET, false), Some(SegmentEnd::Pause));
+    }
+
+    #[test]
+    fn test_segment_ends_at_limit_without_pause() {
+        let mut segmenter = Segmenter::with_limits(5, 30);
+        for step in 1..30 {
+            // Text without audio is not a pause.
+            let has_text = step % 2 == 0;
+            assert_eq!(segmenter.observe(&QUIET, &QUIET, has_text), None);
+        }
+        assert_eq!(segmenter.observe(&LOUD, &LOUD, true), Some(SegmentEnd::Limit));
+    }
+
+    #[test]
+    fn test_limits_stay_below_the_caches() {
+        assert!(Segmenter::new(4096).max_steps() < 4096);
+        assert!(Segmenter::new(100_000).max_steps() < 4096);
+        assert!(Segmenter::new(1000).max_steps() < 1000);
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tone;
+    use hibiki::serve;
+    use hibiki::stream::EchoProcessor;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::{Message, WebSocket};
+
+    type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    struct Server {
+        addr: SocketAddr,
+        shutdown: Arc<AtomicBool>,
+        handle: Option<JoinHandle<()>>,
+    }
+
+    impl Server {
+        fn start(max_connections: usize) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let server_shutdown = shutdown.clone();
+            let handle = std::thread::spawn(move || {
+                serve::serve(
+                    listener,
+                    max_connections,
+                    || Ok(EchoProcessor::new(2)),
+                    server_shutdown,
+                )
+                .unwrap()
+            });
+            Self { addr, shutdown, handle: Some(handle) }
+        }
+
+        fn connect(&self, query: &str) -> tungstenite::Result<Client> {
+            tungstenite::connect(format!("ws://{}/?{query}", self.addr)).map(|(ws, _)| ws)
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::Relaxed);
+            if let Some(handle) = self.handle.take() {
+                handle.join().unwrap()
+            }
+        }
+    }
+
+    /// Reads until the server closes the connection, returning the events and
+    /// the binary messages.
+    fn read_all(ws: &mut Client) -> (Vec<serde_json::Value>, Vec<Vec<u8>>) {
+        let (mut events, mut audio) = (vec![], vec![]);
+        loop {
+            match ws.read() {
+                Ok(Message::Text(text)) => {
+                    events.push(serde_json::from_str(text.as_str()).unwrap())
+                }
+                Ok(Message::Binary(data)) => audio.push(data.to_vec()),
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed) => break,
+                Err(e) => panic!("{e}"),
+            }
+        }
+        (events, audio)
+    }
+
+    fn end(ws: &mut Client) {
+        ws.send(Message::text(r#"{"command": "end"}"#)).unwrap();
+    }
+
+    fn check_session(events: &[serde_json::Value]) -> usize {
+        assert_eq!(events[0]["event"], "session_start");
+        assert_eq!(events[0]["mode"], "serve");
+        let last = events.last().unwrap();
+        assert_eq!(last["event"], "session_end");
+        let audio_events: usize = events
+            .iter()
+            .filter(|e| e["event"] == "audio")
+            .map(|e| e["length"].as_u64().unwrap() as usize)
+            .sum();
+        let samples = last["samples_generated"].as_u64().unwrap() as usize;
+        assert_eq!(audio_events, samples);
+        samples
+    }
+
+    #[test]
+    fn test_pcm_session() {
+        let server = Server::start(2);
+        let mut ws = server.connect("format=s16le&sample_rate=48000&channels=2").unwrap();
+        let pcm: Vec<f32> = tone(48_000, 48_000).collect();
+        let stereo: Vec<u8> =
+            pcm.iter().flat_map(|s| ((s * 32767.) as i16).to_le_bytes().repeat(2)).collect();
+        for chunk in stereo.chunks(4 * 4800) {
+            ws.send(Message::binary(chunk.to_vec())).unwrap();
+        }
+        // Half a sample is rejected without ending the session.
+        ws.send(Message::binary(vec![0u8; 3])).unwrap();
+        end(&mut ws);
+        let (events, audio) = read_all(&mut ws);
+
+        assert!(events.iter().any(|e| e["event"] == "error"));
+        let samples = check_session(&events);
+        let received: usize = audio.iter().map(|a| a.len() / 2).sum();
+        assert_eq!(received, samples);
+        // 1s of input and the tail, minus the two frames of echo delay.
+        assert!(samples >= 24_000, "{samples}");
+    }
+
+    #[test]
+    fn test_opus_session() {
+        let server = Server::start(1);
+        let mut ws = server.connect("format=opus").unwrap();
+        let mut encoder =
+            opus_rs::OpusEncoder::new(24_000, 1, opus_rs::Application::Audio).unwrap();
+        let mut packet = [0u8; 1276];
+        let pcm: Vec<f32> = tone(24_000, 24_000).collect();
+        for frame in pcm.chunks_exact(480) {
+            let len = encoder.encode(frame, 480, &mut packet).unwrap();
+            ws.send(Message::binary(packet[..len].to_vec())).unwrap();
+        }
+        end(&mut ws);
+        let (events, audio) = read_all(&mut ws);
+
+        let samples = check_session(&events);
+        let mut decoder = opus_rs::OpusDecoder::new(24_000, 1).unwrap();
+        let mut pcm = [0f32; 2880];
+        let decoded: usize = audio.iter().map(|p| decoder.decode(p, 2880, &mut pcm).unwrap()).sum();
+        // The last packet is padded with silence.
+        assert!(decoded >= samples && decoded < samples + 480, "{decoded} {samples}");
+    }
+
+    #[test]
+    fn test_reset_restarts_the_session() {
+        let server = Server::start(1);
+        let mut ws = server.connect("").unwrap();
+        // Less than a frame of tone, then silence in the new session.
+        let partial: Vec<u8> = tone(24_000, 1200).flat_map(|s| s.to_le_bytes()).collect();
+        let silence: Vec<u8> = vec![0u8; 4 * 12_000];
+        ws.send(Message::binary(partial)).unwrap();
+        ws.send(Message::text(r#"{"command": "reset"}"#)).unwrap();
+        ws.send(Message::binary(silence)).unwrap();
+        end(&mut ws);
+        let (events, audio) = read_all(&mut ws);
+
+        let starts: Vec<usize> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e["event"] == "session_start")
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(starts.len(), 2);
+        // The first session ends before any frame was complete.
+        assert_eq!(events[starts[1] - 1]["event"], "session_end");
+        assert_eq!(events[starts[1] - 1]["samples_generated"], 0);
+        // Steps count from zero again after the reset.
+        assert_eq!(events[starts[1] + 1]["step"], 0);
+        check_session(&events[starts[1]..]);
+        // Nothing of the tone buffered before the reset is translated.
+        let samples: Vec<f32> = audio
+            .iter()
+            .flat_map(|a| a.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect();
+        assert!(!samples.is_empty());
+        assert!(samples.iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_connection_limit_and_bad_requests() {
+        let server = Server::start(1);
+        let status = |result: tungstenite::Result<Client>| match result {
+            Err(tungstenite::Error::Http(response)) => response.status().as_u16(),
+            Err(e) => panic!("{e}"),
+            Ok(_) => panic!("connection accepted"),
+        };
+        assert_eq!(status(server.connect("format=mp3")), 400);
+
+        let first = server.connect("format=f32le").unwrap();
+        assert_eq!(status(server.connect("format=f32le")), 503);
+        drop(first);
+        // The slot is released once the server notices the disconnection.
+        let mut retries = 0;
+        let mut second = loop {
+            match server.connect("format=f32le") {
+                Ok(ws) => break ws,
+                Err(_) if retries < 50 => retries += 1,
+                Err(e) => panic!("{e}"),
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        };
+        end(&mut second);
+        let (events, audio) = read_all(&mut second);
+        assert_eq!(check_session(&events), audio.iter().map(|a| a.len() / 4).sum::<usize>());
+    }
+
+    #[test]
+    fn test_shutdown_drops_clients_that_do_not_close() {
+        let server = Server::start(1);
+        // The client never reads, so it never answers the close frame.
+        let _ws = server.connect("").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            drop(server);
+            done_tx.send(()).unwrap();
+        });
+        // The connection is dropped after the 10s close handshake timeout.
+        done_rx.recv_timeout(std::time::Duration::from_secs(20)).unwrap();
+    }
+}
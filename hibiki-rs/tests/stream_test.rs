@@ -2,8 +2,11 @@
 // This source code is licensed under the license found in the
 // LICENSE file in the root directory of this source tree.
 
+mod common;
+
 #[cfg(test)]
 mod tests {
+    use crate::common::{run_echo, run_echo_on_file, tone, wav_bytes, TempDir};
     use std::path::PathBuf;
 
     #[test]
@@ -76,7 +79,374 @@ mod tests {
             spec.sample_rate, spec.channels, spec.bits_per_sample);
         
         // Basic sanity checks
-        assert!(samples.len() > 0, "WAV file should not be empty");
+        assert!(!samples.is_empty(), "WAV file should not be empty");
         assert!(duration_s > 0.5, "Duration should be reasonable (>0.5s)");
     }
+
+    #[test]
+    fn test_echo_processor_delays_frames() {
+        use hibiki::stream::{EchoProcessor, FrameProcessor};
+        use hibiki::FRAME_SIZE;
+
+        let mut echo = EchoProcessor::new(2);
+        let frames: Vec<[f32; FRAME_SIZE]> = (0..4).map(|i| [i as f32; FRAME_SIZE]).collect();
+        let outputs: Vec<Vec<f32>> =
+            frames.iter().map(|f| echo.process_frame(f).unwrap().0).collect();
+
+        assert!(outputs[0].is_empty());
+        assert!(outputs[1].is_empty());
+        assert_eq!(outputs[2], frames[0].to_vec());
+        assert_eq!(outputs[3], frames[1].to_vec());
+        assert_eq!(echo.stats().frames_processed, 4);
+    }
+
+    #[test]
+    fn test_reset_starts_an_independent_session() {
+        use hibiki::stream::{EchoProcessor, FrameProcessor};
+        use hibiki::FRAME_SIZE;
+
+        let mut echo = EchoProcessor::new(2);
+        for i in 0..5 {
+            echo.process_frame(&[i as f32; FRAME_SIZE]).unwrap();
+        }
+        echo.reset();
+        assert_eq!(echo.stats().frames_processed, 0);
+
+        // Nothing from the previous session comes out of the delay line and the
+        // frame count, used for the text, starts over.
+        let (pcm, text) = echo.process_frame(&[7.0; FRAME_SIZE]).unwrap();
+        assert!(pcm.is_empty());
+        assert_eq!(text.as_deref(), Some(" [0]"));
+        assert!(echo.process_frame(&[8.0; FRAME_SIZE]).unwrap().0.is_empty());
+        assert_eq!(echo.process_frame(&[9.0; FRAME_SIZE]).unwrap().0, vec![7.0; FRAME_SIZE]);
+    }
+
+    #[test]
+    fn test_file_to_wav_with_mock_processor() {
+        use hibiki::stream::StreamConfig;
+
+        // 0.5s of input + 0.5s of padding added by the file reader = 13 frames.
+        let dir = run_echo_on_file("mock", tone(24_000, 12_000), |dir| StreamConfig {
+            save_output: Some(dir.join("output.wav")),
+            ..Default::default()
+        });
+
+        let reader = hound::WavReader::open(dir.join("output.wav")).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.sample_rate, 24_000);
+        assert_eq!(reader.len() as usize, (13 - 2) * hibiki::FRAME_SIZE);
+    }
+
+    #[test]
+    fn test_input_errors_fail_the_stream() {
+        use hibiki::stream::{run_with_shutdown, EchoProcessor, StreamConfig};
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        let dir = TempDir::new("input_error");
+        let input_file = dir.join("input.wav");
+        std::fs::write(&input_file, b"not an audio file").unwrap();
+        let config = StreamConfig {
+            input_file: Some(input_file),
+            disable_speaker: true,
+            ..Default::default()
+        };
+        let shutdown = Arc::new(AtomicBool::new(false));
+        assert!(run_with_shutdown(config, EchoProcessor::new(2), shutdown).is_err());
+    }
+
+    #[test]
+    fn test_unpaced_file_input_is_faster_than_real_time() {
+        use hibiki::stream::StreamConfig;
+
+        // 20s of input + 0.5s of padding = 257 frames (the last one partial).
+        let start = std::time::Instant::now();
+        let dir = run_echo_on_file("no_pace", tone(24_000, 20 * 24_000), |dir| StreamConfig {
+            no_pace: true,
+            save_output: Some(dir.join("output.wav")),
+            ..Default::default()
+        });
+        assert!(start.elapsed().as_secs_f32() < 10.0, "unpaced run took {:?}", start.elapsed());
+
+        let reader = hound::WavReader::open(dir.join("output.wav")).unwrap();
+        assert_eq!(reader.len() as usize, (257 - 2) * hibiki::FRAME_SIZE);
+    }
+
+    /// Hands out the data a few bytes at a time, splitting the samples.
+    struct SlowReader {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl std::io::Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(7).min(self.data.len() - self.pos);
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_raw_stdin_input() {
+        use hibiki::stream::raw::run_raw_input;
+        use hibiki::stream::{InputChannel, RawInputFormat, RawSampleFormat};
+        use std::sync::atomic::AtomicBool;
+        use std::sync::{mpsc, Arc};
+
+        // One second of stereo s16le at 48kHz, the left channel at 0.5 and the
+        // right one silent.
+        let mut data = vec![];
+        for _ in 0..48_000 {
+            data.extend_from_slice(&16384i16.to_le_bytes());
+            data.extend_from_slice(&0i16.to_le_bytes());
+        }
+        let format = RawInputFormat {
+            sample_format: RawSampleFormat::S16Le,
+            sample_rate: 48_000,
+            channels: 2,
+        };
+        let (tx, rx) = mpsc::sync_channel(100);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reader = SlowReader { data, pos: 0 };
+        run_raw_input(reader, &format, InputChannel::Mix, false, tx, shutdown).unwrap();
+
+        let frames: Vec<_> = rx.iter().collect();
+        // 1s of audio and 0.5s of padding, the last frame being partial.
+        assert_eq!(frames.len(), 19);
+        // The channels are mixed down to mono.
+        assert!(frames[5].iter().all(|s| (s - 0.25).abs() < 1e-3));
+        assert!(frames[17].iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_raw_output() {
+        use hibiki::stream::raw::run_raw_output;
+        use hibiki::stream::RawSampleFormat;
+        use std::sync::mpsc;
+
+        for (format, bytes) in [(RawSampleFormat::S16Le, 2), (RawSampleFormat::F32Le, 4)] {
+            let (tx, rx) = mpsc::channel();
+            tx.send(vec![0.5; 100]).unwrap();
+            tx.send(vec![-1.5; 20]).unwrap();
+            drop(tx);
+            let mut out = vec![];
+            run_raw_output(&mut out, format, rx).unwrap();
+            assert_eq!(out.len(), 120 * bytes);
+
+            let mut decoded = vec![];
+            format.decode(&out, &mut decoded);
+            assert!((decoded[0] - 0.5).abs() < 1e-4);
+            // Integer samples are clipped.
+            let last = if format == RawSampleFormat::S16Le { -1.0 } else { -1.5 };
+            assert!((decoded[119] - last).abs() < 1e-4);
+        }
+        assert_eq!("F32LE".parse::<RawSampleFormat>().unwrap(), RawSampleFormat::F32Le);
+        assert!("u8".parse::<RawSampleFormat>().is_err());
+    }
+
+    #[test]
+    fn test_stereo_recording_is_aligned() {
+        use hibiki::stream::StreamConfig;
+        use hibiki::FRAME_SIZE;
+
+        let dir = run_echo_on_file("stereo", tone(24_000, 12_000), |dir| StreamConfig {
+            no_pace: true,
+            save_stereo: Some(dir.join("stereo.wav")),
+            ..Default::default()
+        });
+
+        let mut reader = hound::WavReader::open(dir.join("stereo.wav")).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, 24_000);
+        let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        let (left, right): (Vec<i16>, Vec<i16>) = samples.chunks(2).map(|s| (s[0], s[1])).unzip();
+        // Every step is written, the echo fits in the 13 input frames.
+        assert_eq!(left.len(), 13 * FRAME_SIZE);
+        assert!(right[..2 * FRAME_SIZE].iter().all(|s| s.abs() <= 1));
+        // The right channel is the left one delayed by the two frames of the
+        // echo, up to the dither.
+        for (i, &sample) in left[..11 * FRAME_SIZE].iter().enumerate() {
+            let echoed = right[i + 2 * FRAME_SIZE];
+            assert!((echoed as i32 - sample as i32).abs() <= 2, "{i}: {echoed} {sample}");
+        }
+    }
+
+    #[test]
+    fn test_save_input_records_the_model_input() {
+        use hibiki::stream::StreamConfig;
+        use hibiki::FRAME_SIZE;
+
+        let dir = run_echo_on_file("save_input", tone(24_000, 12_000), |dir| StreamConfig {
+            no_pace: true,
+            save_input: Some(dir.join("recording.wav")),
+            ..Default::default()
+        });
+
+        let read = |path: &std::path::Path| -> Vec<i16> {
+            let mut reader = hound::WavReader::open(path).unwrap();
+            assert_eq!(reader.spec().channels, 1);
+            assert_eq!(reader.spec().sample_rate, 24_000);
+            reader.samples::<i16>().map(|s| s.unwrap()).collect()
+        };
+        // The 13 frames sent to the model: the input then the padding.
+        let (original, recorded) = (read(&dir.join("input.wav")), read(&dir.join("recording.wav")));
+        assert_eq!(recorded.len(), 13 * FRAME_SIZE);
+        for (&a, &b) in original.iter().zip(recorded.iter()) {
+            assert!((a as i32 - b as i32).abs() <= 2, "{a} {b}");
+        }
+        assert!(recorded[original.len()..].iter().all(|s| s.abs() <= 1));
+    }
+
+    #[test]
+    fn test_output_config_negotiation() {
+        use cpal::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+        use hibiki::stream::choose_output_config;
+
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(min),
+                SampleRate(max),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let pick = |supported: &[SupportedStreamConfigRange], default| {
+            choose_output_config(supported, default).map(|c| (c.channels(), c.sample_rate().0))
+        };
+
+        // 24kHz is used when supported, with the fewest channels.
+        let supported = [
+            range(2, 8_000, 96_000, SampleFormat::F32),
+            range(1, 8_000, 96_000, SampleFormat::F32),
+        ];
+        assert_eq!(pick(&supported, None), Some((1, 24_000)));
+        // Otherwise the device default, e.g. ALSA hardware stuck at 48kHz stereo.
+        let supported = [
+            range(2, 48_000, 48_000, SampleFormat::F32),
+            range(6, 44_100, 44_100, SampleFormat::F32),
+        ];
+        let default = supported[1].with_max_sample_rate();
+        assert_eq!(pick(&supported, Some(default)), Some((6, 44_100)));
+        // Then the rate closest to 24kHz.
+        assert_eq!(pick(&supported, None), Some((6, 44_100)));
+        let supported = [range(2, 32_000, 192_000, SampleFormat::F32)];
+        assert_eq!(pick(&supported, None), Some((2, 32_000)));
+        // Any sample format can be used, f32 first, then the widest samples.
+        let supported = [range(1, 24_000, 24_000, SampleFormat::I16)];
+        let config = choose_output_config(&supported, None).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+        let supported = [
+            range(2, 48_000, 48_000, SampleFormat::I16),
+            range(2, 48_000, 48_000, SampleFormat::I32),
+            range(2, 48_000, 48_000, SampleFormat::F32),
+        ];
+        let config = choose_output_config(&supported, None).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::F32);
+        let config = choose_output_config(&supported[..2], None).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I32);
+        assert_eq!(pick(&[], None), None);
+    }
+
+    #[test]
+    fn test_input_channel_selection() {
+        use hibiki::stream::raw::run_raw_input;
+        use hibiki::stream::{InputChannel, StreamConfig};
+        use hibiki::stream::{RawInputFormat, RawSampleFormat};
+        use std::sync::atomic::AtomicBool;
+        use std::sync::{mpsc, Arc};
+
+        assert_eq!("mix".parse::<InputChannel>().unwrap(), InputChannel::Mix);
+        assert_eq!("2".parse::<InputChannel>().unwrap(), InputChannel::Index(2));
+        assert!("left".parse::<InputChannel>().is_err());
+
+        // Raw input at 24kHz, 0.5 on the first channel and -0.25 on the second.
+        let mut data = vec![];
+        for _ in 0..24_000 {
+            data.extend_from_slice(&16384i16.to_le_bytes());
+            data.extend_from_slice(&(-8192i16).to_le_bytes());
+        }
+        let format = RawInputFormat {
+            sample_format: RawSampleFormat::S16Le,
+            sample_rate: 24_000,
+            channels: 2,
+        };
+        let raw_frames = |channel| {
+            let (tx, rx) = mpsc::sync_channel(100);
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let reader = std::io::Cursor::new(data.clone());
+            run_raw_input(reader, &format, channel, false, tx, shutdown)?;
+            anyhow::Ok(rx.iter().collect::<Vec<_>>())
+        };
+        let expected = [
+            (InputChannel::Mix, 0.125),
+            (InputChannel::Index(0), 0.5),
+            (InputChannel::Index(1), -0.25),
+        ];
+        for (channel, expected) in expected {
+            let frames = raw_frames(channel).unwrap();
+            assert!(frames[5].iter().all(|s| (s - expected).abs() < 1e-3), "{channel}");
+        }
+        assert!(raw_frames(InputChannel::Index(2)).is_err());
+
+        // Stereo file, the tone on the left channel and silence on the right.
+        let dir = TempDir::new("channel");
+        let input_file = dir.join("input.wav");
+        let stereo = tone(24_000, 12_000).flat_map(|v| [v, 0.]);
+        std::fs::write(&input_file, wav_bytes(24_000, 2, stereo)).unwrap();
+        let peak = |input_channel| {
+            let recording = dir.join("recording.wav");
+            let config = StreamConfig {
+                input_file: Some(input_file.clone()),
+                input_channel,
+                no_pace: true,
+                disable_speaker: true,
+                save_input: Some(recording.clone()),
+                ..Default::default()
+            };
+            run_echo(config);
+            let mut reader = hound::WavReader::open(&recording).unwrap();
+            reader.samples::<i16>().map(|s| s.unwrap().unsigned_abs()).max().unwrap()
+        };
+        assert!(peak(InputChannel::Index(0)).abs_diff(9830) < 10);
+        assert!(peak(InputChannel::Mix).abs_diff(4915) < 10);
+        assert!(peak(InputChannel::Index(1)) <= 1);
+    }
+
+    #[test]
+    fn test_device_selection() {
+        use hibiki::stream::{select_device, DeviceQuery};
+
+        let names: Vec<String> = ["USB Audio", "USB Audio", "USB Audio Pro", "Built-in Microphone"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let select = |query: &str, strict| select_device(&names, &query.parse().unwrap(), strict);
+
+        // Indices are the ones of --list-devices, starting at 1.
+        assert_eq!("2".parse::<DeviceQuery>().unwrap(), DeviceQuery::Index(2));
+        assert!("0".parse::<DeviceQuery>().is_err());
+        assert_eq!(select("2", true).unwrap(), Some(1));
+        assert_eq!(select("5", true).unwrap(), None);
+        // An exact name wins over substrings, which are case-insensitive.
+        assert_eq!(select("USB Audio Pro", true).unwrap(), Some(2));
+        assert_eq!(select("built-in", true).unwrap(), Some(3));
+        assert_eq!(select("headset", false).unwrap(), None);
+        // Identical names only fall back to the first one when not strict.
+        assert_eq!(select("USB Audio", false).unwrap(), Some(0));
+        assert!(select("USB Audio", true).is_err());
+        assert!(select("usb", true).is_err());
+        // A device picked by index is found again by its name and its rank
+        // among the devices sharing that name, even once indices shifted.
+        let pinned = DeviceQuery::pinned(&names, 1);
+        assert_eq!(pinned, DeviceQuery::Pinned { name: "USB Audio".to_string(), occurrence: 2 });
+        let shifted: Vec<String> = ["Built-in Microphone", "USB Audio", "USB Audio"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(select_device(&shifted, &pinned, true).unwrap(), Some(2));
+        assert_eq!(select_device(&shifted[..2], &pinned, true).unwrap(), None);
+    }
 }
@@ -0,0 +1,101 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use hibiki::subtitles::{SubtitleFormat, SubtitleWriter};
+    use hibiki::{FRAME_SIZE, TARGET_SAMPLE_RATE};
+    use std::path::Path;
+
+    fn render(format: SubtitleFormat, pieces: &[(usize, &str)]) -> String {
+        let mut writer = SubtitleWriter::new(Vec::new(), format).unwrap();
+        for (sample, text) in pieces {
+            writer.push(*sample, text).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_cues_break_on_punctuation_and_pauses() {
+        let step = FRAME_SIZE;
+        let pieces = [
+            (0, " Hello"),
+            (step, " world."),
+            (3 * step, " How"),
+            (4 * step, " are"),
+            // 26 steps (2.08s) of silence.
+            (30 * step, " you"),
+        ];
+        let srt = render(SubtitleFormat::Srt, &pieces);
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:00,240\nHello world.\n\n\
+             2\n00:00:00,240 --> 00:00:01,820\nHow are\n\n\
+             3\n00:00:02,400 --> 00:00:03,900\nyou\n\n"
+        );
+    }
+
+    #[test]
+    fn test_long_cues_break_on_word_boundaries() {
+        let pieces: Vec<(usize, &str)> =
+            (0..40).map(|i| (i * FRAME_SIZE, if i % 2 == 0 { " word" } else { "ing" })).collect();
+        let srt = render(SubtitleFormat::Srt, &pieces);
+        let lines: Vec<&str> = srt.lines().filter(|l| l.starts_with("word")).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| l.split(' ').all(|w| w == "wording")));
+    }
+
+    #[test]
+    fn test_webvtt_header_and_timestamps() {
+        let vtt = render(SubtitleFormat::WebVtt, &[(3_600 * TARGET_SAMPLE_RATE, " Bonjour!")]);
+        assert_eq!(vtt, "WEBVTT\n\n01:00:00.000 --> 01:00:01.500\nBonjour!\n\n");
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(SubtitleFormat::from_path(Path::new("a.SRT")).unwrap(), SubtitleFormat::Srt);
+        assert_eq!(SubtitleFormat::from_path(Path::new("a.vtt")).unwrap(), SubtitleFormat::WebVtt);
+        assert!(SubtitleFormat::from_path(Path::new("a.txt")).is_err());
+    }
+
+    #[test]
+    fn test_stream_writes_subtitles() {
+        use crate::common::run_echo_on_file;
+        use hibiki::stream::StreamConfig;
+
+        // 3s of silence + 0.5s of padding = 44 frames, the echo processor emits
+        // text on frames 0 and 25.
+        let silence = std::iter::repeat_n(0., 3 * 24_000);
+        let dir = run_echo_on_file("subtitles", silence, |dir| StreamConfig {
+            no_pace: true,
+            subtitles: Some(dir.join("output.srt")),
+            ..Default::default()
+        });
+
+        // Text is timed against the generated audio, which starts 2 frames late.
+        let srt = std::fs::read_to_string(dir.join("output.srt")).unwrap();
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,500\n[0]\n\n\
+             2\n00:00:01,920 --> 00:00:03,420\n[25]\n\n"
+        );
+    }
+
+    #[test]
+    fn test_cut_ends_the_cue() {
+        let mut writer = SubtitleWriter::new(Vec::new(), SubtitleFormat::Srt).unwrap();
+        writer.push(0, " Hello").unwrap();
+        writer.push(FRAME_SIZE, " there").unwrap();
+        writer.cut().unwrap();
+        writer.push(5 * FRAME_SIZE, " again").unwrap();
+        let srt = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,580\nHello there\n\n\
+             2\n00:00:00,400 --> 00:00:01,900\nagain\n\n"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) Kyutai, all rights reserved.
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+#[cfg(test)]
+mod tests {
+    use hibiki::stream::vad::{SilenceAction, Vad, VadConfig, VadEvent};
+    use hibiki::FRAME_SIZE;
+    use std::time::Duration;
+
+    const LOUD: [f32; FRAME_SIZE] = [0.1; FRAME_SIZE];
+    const QUIET: [f32; FRAME_SIZE] = [0.001; FRAME_SIZE];
+
+    fn config() -> VadConfig {
+        VadConfig {
+            hangover: Duration::from_millis(240),
+            long_silence: Duration::from_millis(800),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_utterance_boundaries_with_hangover() {
+        let mut vad = Vad::new(&config());
+        assert_eq!(vad.push(&QUIET, false), None);
+        // A single loud frame is not an utterance.
+        assert_eq!(vad.push(&LOUD, false), None);
+        assert_eq!(vad.push(&QUIET, false), None);
+        assert_eq!(vad.push(&LOUD, false), None);
+        assert_eq!(vad.push(&LOUD, false), Some(VadEvent::UtteranceStart { step: 3 }));
+        assert!(vad.is_speech());
+        // Pauses shorter than the hangover do not split the utterance.
+        for _ in 0..2 {
+            assert_eq!(vad.push(&QUIET, true), None);
+        }
+        assert_eq!(vad.push(&LOUD, true), None);
+        assert_eq!(vad.push(&QUIET, true), None);
+        assert_eq!(vad.push(&QUIET, true), None);
+        assert!(vad.is_speech());
+        assert_eq!(vad.push(&QUIET, true), Some(VadEvent::UtteranceEnd { step: 8 }));
+        assert!(!vad.is_speech());
+    }
+
+    #[test]
+    fn test_long_silence_waits_for_the_translation() {
+        let mut vad = Vad::new(&config());
+        vad.push(&LOUD, false);
+        vad.push(&LOUD, false);
+        for _ in 0..3 {
+            vad.push(&QUIET, true);
+        }
+        // The model is still translating the utterance.
+        for _ in 0..20 {
+            assert_eq!(vad.push(&QUIET, true), None);
+        }
+        for _ in 0..9 {
+            assert_eq!(vad.push(&QUIET, false), None);
+            assert!(!vad.is_idle());
+        }
+        assert_eq!(vad.push(&QUIET, false), Some(VadEvent::LongSilence { step: 34 }));
+        assert!(vad.is_idle());
+        assert_eq!(vad.push(&QUIET, false), None);
+        assert!(vad.is_idle());
+        // The first loud frame wakes the model up, before the utterance starts.
+        assert_eq!(vad.push(&LOUD, false), None);
+        assert!(!vad.is_idle());
+        assert_eq!(vad.push(&LOUD, false), Some(VadEvent::UtteranceStart { step: 36 }));
+    }
+
+    #[test]
+    fn test_vad_config() {
+        assert!(VadConfig::default().validate().is_ok());
+        assert!(VadConfig { threshold_db: 3., ..config() }.validate().is_err());
+        let long_silence = Duration::from_millis(200);
+        assert!(VadConfig { long_silence, ..config() }.validate().is_err());
+        assert_eq!("Reset".parse::<SilenceAction>().unwrap(), SilenceAction::Reset);
+        assert!("pause".parse::<SilenceAction>().is_err());
+    }
+}